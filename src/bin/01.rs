@@ -3,7 +3,7 @@ use std::io::{self, BufRead, BufReader};
 use std::cmp::Ordering;
 
 fn main() -> io::Result<()> {
-    let input = std::env::args().skip(1).next().expect("Specify an input");
+    let input = std::env::args().nth(1).expect("Specify an input");
     println!("Input is {}", input);
     let f = File::open(input)?;
     let f = BufReader::new(f);
@@ -15,7 +15,7 @@ fn main() -> io::Result<()> {
     Ok(())
 }
 
-fn part1(numbers: &Vec<i64>) {
+fn part1(numbers: &[i64]) {
     let l = numbers.len();
 
     let mut i = 0;
@@ -33,7 +33,7 @@ fn part1(numbers: &Vec<i64>) {
     };
 }
 
-fn part2(numbers: &Vec<i64>) {
+fn part2(numbers: &[i64]) {
     let l = numbers.len();
 
     let mut i = 0;
@@ -3,22 +3,22 @@ use std::io::{self, BufRead, BufReader};
 
 
 fn main() -> io::Result<()> {
-    let input = std::env::args().skip(1).next().expect("Specify an input");
+    let input = std::env::args().nth(1).expect("Specify an input");
     println!("Input is {}", input);
     let f = File::open(input)?;
     let f = BufReader::new(f);
 
-    let lines = f.lines().filter_map(|l| l.ok()).collect::<Vec<String>>();
+    let lines = f.lines().collect::<io::Result<Vec<String>>>()?;
 
     let trees = solve(&lines, 3, 1);
     println!("Part 01: {}", trees);
 
-    println!("Part 02: {}", vec![solve(&lines, 1, 1), solve(&lines, 3, 1), solve(&lines, 5, 1), solve(&lines, 7, 1), solve(&lines, 1, 2)].iter().fold(1, |x, y| x * y));
+    println!("Part 02: {}", [solve(&lines, 1, 1), solve(&lines, 3, 1), solve(&lines, 5, 1), solve(&lines, 7, 1), solve(&lines, 1, 2)].iter().product::<usize>());
 
     Ok(())
 }
 
-fn solve(lines: &Vec<String>, right: usize, down: usize) -> usize {
+fn solve(lines: &[String], right: usize, down: usize) -> usize {
     let mut offset = 0;
     let mut trees = 0;
     let mut skip = 0;
@@ -29,7 +29,7 @@ fn solve(lines: &Vec<String>, right: usize, down: usize) -> usize {
         }
 
         skip = down - 1;
-        if '#' == line.chars().cycle().skip(offset).next().unwrap() {
+        if '#' == line.chars().cycle().nth(offset).unwrap() {
             trees += 1;
         }
         offset += right;
@@ -4,117 +4,448 @@ use std::io::{self, Read};
 use regex::Regex;
 
 use std::collections::HashMap;
+use std::fmt;
 
 fn main() -> io::Result<()> {
-    let path = env::args().skip(1).next().expect("Specify an input file");
+    let mut args = env::args().skip(1);
+    let path = args.next().expect("Specify an input file");
+    let mode = args.next();
     let mut file = File::open(path)?;
     let mut s = String::new();
     file.read_to_string(&mut s)?;
 
-    let passports = s.split("\n\n").map(|entry| {
-        let mut pass = HashMap::new();
+    let entries = parse_batch(&s);
+    if mode.as_deref() == Some("diagnostics") {
+        print!("{}", diagnostics(&entries));
+        return Ok(())
+    }
 
-        entry.split_whitespace().for_each(|kv| {
-            let mut it = kv.split(":");
-            let k = it.next().unwrap();
-            let v = it.next().unwrap();
-            pass.insert(k, v);
-        });
+    let passports = entries.into_iter().collect::<Result<Vec<_>, _>>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    match mode.as_deref() {
+        None => {
+            println!("Part 01: {}", passports.iter().filter(|p| valid_part1(p)).count());
+            println!("Part 02: {}", passports.iter().filter(|p| valid_part2(p)).count());
+        },
+        Some("json") => passports.iter().for_each(|p| println!("{}", p.to_json())),
+        Some("csv") => {
+            println!("{}", csv_header());
+            passports.iter().for_each(|p| println!("{}", p.to_csv()));
+        },
+        Some("normalize") => {
+            let width = args.next().map(|w| w.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid width '{}'", w)))).transpose()?;
+            let order = args.next();
+            let order = order.as_deref().map(|o| o.split(',').collect::<Vec<_>>()).unwrap_or_else(|| FIELDS.to_vec());
+            print!("{}", write_batch(&passports, &WriteOptions { width, order: &order }));
+        },
+        Some(other) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown mode '{}', expected one of diagnostics, json, csv, normalize", other))),
+    }
 
-        pass
-    }).collect::<Vec<_>>();
+    Ok(())
+}
 
-    println!("Part 01: {}", passports.iter().filter(|p| p.len() == 8 || (p.len() == 7 && !p.contains_key("cid"))).count());
-    println!("Part 02: {}", passports.iter().filter(|p| valid_part2(p)).count());
+/// Splits a batch into its blank-line separated entries, along with the line
+/// number (starting at 1) on which each entry begins.
+fn entries(s: &str) -> Vec<(usize, &str)> {
+    let mut line = 1;
+    s.split("\n\n").map(|entry| {
+        let trimmed = entry.trim_start_matches('\n');
+        let start = line + entry.len() - trimmed.len();
+        line += entry.matches('\n').count() + 2;
+        (start, trimmed)
+    }).filter(|(_, entry)| !entry.trim().is_empty()).collect()
+}
 
-    Ok(())
+const REQUIRED: [&str; 7] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid"];
+const FIELDS: [&str; 8] = ["byr", "iyr", "eyr", "hgt", "hcl", "ecl", "pid", "cid"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ParseErrorKind {
+    MissingColon(String),
+    EmptyKey(String),
+    DuplicateKey(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ParseError {
+    line: usize,
+    kind: ParseErrorKind,
 }
 
-fn valid_part2(passport: &HashMap<&str, &str>) -> bool {
-    if let Some(yr) = passport.get("byr") {
-        if yr < &"1920" || yr > &"2002" {
-            return false
+impl ParseErrorKind {
+    fn kind(&self) -> &'static str {
+        match self {
+            ParseErrorKind::MissingColon(_) => "missing colon",
+            ParseErrorKind::EmptyKey(_) => "empty key",
+            ParseErrorKind::DuplicateKey(_) => "duplicate key",
         }
-    } else {
-        return false
     }
+}
 
-    if let Some(yr) = passport.get("iyr") {
-        if yr < &"2010" || yr > &"2020" {
-            return false
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::MissingColon(token) => write!(f, "expected key:value, got '{}'", token),
+            ParseErrorKind::EmptyKey(token) => write!(f, "empty key in '{}'", token),
+            ParseErrorKind::DuplicateKey(key) => write!(f, "duplicate key '{}'", key),
         }
-    } else {
-        return false
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A single passport record. Known fields get their own slot, anything else
+/// is kept in `extra` in the order it appeared.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Passport<'a> {
+    line: usize,
+    byr: Option<&'a str>,
+    iyr: Option<&'a str>,
+    eyr: Option<&'a str>,
+    hgt: Option<&'a str>,
+    hcl: Option<&'a str>,
+    ecl: Option<&'a str>,
+    pid: Option<&'a str>,
+    cid: Option<&'a str>,
+    extra: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Passport<'a> {
+    /// Parses one batch entry starting on `line`. Values are everything after
+    /// the first colon, so they may contain colons themselves.
+    fn parse(line: usize, entry: &'a str) -> Result<Self, ParseError> {
+        let mut passport = Passport { line, ..Default::default() };
+
+        for (offset, l) in entry.lines().enumerate() {
+            for kv in l.split_whitespace() {
+                let error = |kind| ParseError { line: line + offset, kind };
+                let (k, v) = kv.split_once(':').ok_or_else(|| error(ParseErrorKind::MissingColon(kv.to_string())))?;
+                if k.is_empty() {
+                    return Err(error(ParseErrorKind::EmptyKey(kv.to_string())))
+                }
+
+                if passport.get(k).is_some() {
+                    return Err(error(ParseErrorKind::DuplicateKey(k.to_string())))
+                }
+
+                match passport.slot(k) {
+                    Some(slot) => *slot = Some(v),
+                    None => passport.extra.push((k, v)),
+                }
+            }
+        }
+
+        Ok(passport)
+    }
+
+    fn slot(&mut self, field: &str) -> Option<&mut Option<&'a str>> {
+        match field {
+            "byr" => Some(&mut self.byr),
+            "iyr" => Some(&mut self.iyr),
+            "eyr" => Some(&mut self.eyr),
+            "hgt" => Some(&mut self.hgt),
+            "hcl" => Some(&mut self.hcl),
+            "ecl" => Some(&mut self.ecl),
+            "pid" => Some(&mut self.pid),
+            "cid" => Some(&mut self.cid),
+            _ => None,
+        }
+    }
+
+    fn get(&self, field: &str) -> Option<&'a str> {
+        match field {
+            "byr" => self.byr,
+            "iyr" => self.iyr,
+            "eyr" => self.eyr,
+            "hgt" => self.hgt,
+            "hcl" => self.hcl,
+            "ecl" => self.ecl,
+            "pid" => self.pid,
+            "cid" => self.cid,
+            _ => self.extra.iter().find(|(k, _)| *k == field).map(|(_, v)| *v),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = format!("{{\"line\":{}", self.line);
+        for field in FIELDS.iter() {
+            let value = self.get(field).map(json_string).unwrap_or_else(|| "null".to_string());
+            out.push_str(&format!(",{}:{}", json_string(field), value));
+        }
+        let extra = self.extra.iter().map(|(k, v)| format!("{}:{}", json_string(k), json_string(v))).collect::<Vec<_>>();
+        out.push_str(&format!(",\"extra\":{{{}}},\"valid\":{}}}", extra.join(","), valid_part2(self)));
+        out
     }
 
-    if let Some(yr) = passport.get("eyr") {
-        if yr < &"2020" || yr > &"2030" {
-            return false
+    fn to_csv(&self) -> String {
+        let mut columns = vec![self.line.to_string()];
+        columns.extend(FIELDS.iter().map(|field| csv_field(self.get(field).unwrap_or(""))));
+        let extra = self.extra.iter().map(|(k, v)| format!("{}:{}", k, v)).collect::<Vec<_>>();
+        columns.push(csv_field(&extra.join(" ")));
+        columns.push(valid_part2(self).to_string());
+        columns.join(",")
+    }
+}
+
+fn csv_header() -> String {
+    format!("line,{},extra,valid", FIELDS.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
         }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
     } else {
-        return false
+        s.to_string()
     }
+}
 
-    if let Some(h) = passport.get("hgt") {
-        if h.ends_with("in") {
-            if h < &"59in" || h > &"76cm" {
-                return false
-            }
-        } else if h.ends_with("cm") {
-            if h < &"150cm" || h > &"193cm" {
-                return false
+/// Parses every entry of a batch, so one malformed entry does not hide the
+/// others
+fn parse_batch(s: &str) -> Vec<Result<Passport<'_>, ParseError>> {
+    entries(s).into_iter().map(|(line, entry)| Passport::parse(line, entry)).collect()
+}
+
+/// Layout used when writing passports back to the batch format
+struct WriteOptions<'o> {
+    /// Maximum line length, or everything on one line when `None`. A field
+    /// longer than the width still gets a line of its own.
+    width: Option<usize>,
+    /// Fields written first, in this order, each only once. Remaining known
+    /// fields follow in `FIELDS` order, then unknown ones in the order they
+    /// were read.
+    order: &'o [&'o str],
+}
+
+impl Default for WriteOptions<'_> {
+    fn default() -> Self {
+        WriteOptions { width: None, order: &FIELDS }
+    }
+}
+
+fn write_passport(passport: &Passport, options: &WriteOptions) -> String {
+    let mut keys: Vec<&str> = vec![];
+    for k in options.order.iter().chain(FIELDS.iter()).copied().chain(passport.extra.iter().map(|(k, _)| *k)) {
+        if passport.get(k).is_some() && !keys.contains(&k) {
+            keys.push(k);
+        }
+    }
+
+    let mut out = String::new();
+    let mut line_len = 0;
+    for k in keys {
+        let token = format!("{}:{}", k, passport.get(k).unwrap());
+        if line_len > 0 {
+            if options.width.map(|w| line_len + 1 + token.len() > w).unwrap_or(false) {
+                out.push('\n');
+                line_len = 0;
+            } else {
+                out.push(' ');
+                line_len += 1;
             }
-        } else {
-            return false
         }
-    } else {
-        return false
+        line_len += token.len();
+        out.push_str(&token);
     }
 
-    if let Some(c) = passport.get("hcl") {
-        let re = Regex::new("^#[0-9a-f]{6}$").unwrap();
-        if !re.is_match(c) {
-            return false
+    out
+}
+
+/// Writes passports as blank-line separated entries, ending with a newline
+fn write_batch(passports: &[Passport], options: &WriteOptions) -> String {
+    let mut out = passports.iter().map(|p| write_passport(p, options)).collect::<Vec<_>>().join("\n\n");
+    out.push('\n');
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Reason {
+    NotANumber,
+    BelowMin(u32),
+    AboveMax(u32),
+    MissingUnit,
+    UnknownUnit,
+    BadFormat(&'static str),
+}
+
+impl Reason {
+    fn kind(&self) -> &'static str {
+        match self {
+            Reason::NotANumber => "not a number",
+            Reason::BelowMin(_) => "below min",
+            Reason::AboveMax(_) => "above max",
+            Reason::MissingUnit => "missing unit",
+            Reason::UnknownUnit => "unknown unit",
+            Reason::BadFormat(_) => "bad format",
         }
-    } else {
-        return false
     }
+}
 
-    if let Some(c) = passport.get("ecl") {
-        let re = Regex::new("^(amb|blu|brn|gry|grn|hzl|oth)$").unwrap();
-        if !re.is_match(c) {
-            return false
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::BelowMin(min) => write!(f, "below min {}", min),
+            Reason::AboveMax(max) => write!(f, "above max {}", max),
+            Reason::BadFormat(expected) => write!(f, "bad format, expected {}", expected),
+            other => write!(f, "{}", other.kind()),
         }
-    } else {
-        return false
     }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Problem<'a> {
+    /// The entry could not be parsed into a passport at all
+    Unparsable(ParseErrorKind),
+    Missing(&'static str),
+    Invalid { field: &'static str, value: &'a str, reason: Reason },
+}
 
-    if let Some(p) = passport.get("pid") {
-        let re = Regex::new("^\\d{9}$").unwrap();
-        if !re.is_match(p) {
-            return false
+impl Problem<'_> {
+    /// The problem without the offending value, used to group similar failures
+    fn kind(&self) -> String {
+        match self {
+            Problem::Unparsable(kind) => kind.kind().to_string(),
+            Problem::Missing(field) => format!("missing {}", field),
+            Problem::Invalid { field, reason, .. } => format!("{} {}", field, reason.kind()),
         }
+    }
+}
+
+impl fmt::Display for Problem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Unparsable(kind) => write!(f, "{}", kind),
+            Problem::Missing(field) => write!(f, "missing {}", field),
+            Problem::Invalid { field, value, reason } => write!(f, "{} {} {}", field, value, reason),
+        }
+    }
+}
+
+fn check_range(value: &str, min: u32, max: u32) -> Result<(), Reason> {
+    let n = value.parse::<u32>().map_err(|_| Reason::NotANumber)?;
+    if n < min {
+        Err(Reason::BelowMin(min))
+    } else if n > max {
+        Err(Reason::AboveMax(max))
     } else {
-        return false
+        Ok(())
+    }
+}
+
+fn check_year(value: &str, min: u32, max: u32) -> Result<(), Reason> {
+    if !Regex::new("^\\d{4}$").unwrap().is_match(value) {
+        return Err(Reason::BadFormat("four digits"))
     }
-    true
+
+    check_range(value, min, max)
+}
+
+fn check_height(value: &str) -> Result<(), Reason> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (n, unit) = value.split_at(split);
+    match unit {
+        "cm" => check_range(n, 150, 193),
+        "in" => check_range(n, 59, 76),
+        "" => Err(Reason::MissingUnit),
+        _ if n.is_empty() => Err(Reason::NotANumber),
+        _ => Err(Reason::UnknownUnit),
+    }
+}
+
+fn check_field(field: &str, value: &str) -> Result<(), Reason> {
+    match field {
+        "byr" => check_year(value, 1920, 2002),
+        "iyr" => check_year(value, 2010, 2020),
+        "eyr" => check_year(value, 2020, 2030),
+        "hgt" => check_height(value),
+        "hcl" if !Regex::new("^#[0-9a-f]{6}$").unwrap().is_match(value) => Err(Reason::BadFormat("# followed by six hex digits")),
+        "ecl" if !Regex::new("^(amb|blu|brn|gry|grn|hzl|oth)$").unwrap().is_match(value) => Err(Reason::BadFormat("one of amb, blu, brn, gry, grn, hzl, oth")),
+        "pid" if !Regex::new("^\\d{9}$").unwrap().is_match(value) => Err(Reason::BadFormat("nine digits")),
+        _ => Ok(()),
+    }
+}
+
+/// Every missing or invalid required field of a passport, in `REQUIRED` order
+fn problems<'a>(passport: &Passport<'a>) -> Vec<Problem<'a>> {
+    REQUIRED.iter().filter_map(|&field| {
+        match passport.get(field) {
+            None => Some(Problem::Missing(field)),
+            Some(value) => check_field(field, value).err().map(|reason| Problem::Invalid { field, value, reason }),
+        }
+    }).collect()
+}
+
+fn valid_part1(passport: &Passport) -> bool {
+    REQUIRED.iter().all(|field| passport.get(field).is_some())
+}
+
+fn valid_part2(passport: &Passport) -> bool {
+    problems(passport).is_empty()
+}
+
+/// Lists the problems of every invalid passport, followed by a histogram of
+/// the failure reasons across the whole batch. An entry that could not be
+/// parsed is reported on the line of its parse error.
+fn diagnostics(passports: &[Result<Passport, ParseError>]) -> String {
+    let mut out = String::new();
+    let mut histogram: HashMap<String, usize> = HashMap::new();
+    let mut total = 0;
+    let mut invalid = 0;
+
+    for passport in passports {
+        total += 1;
+        let (line, problems) = match passport {
+            Ok(passport) => (passport.line, problems(passport)),
+            Err(e) => (e.line, vec![Problem::Unparsable(e.kind.clone())]),
+        };
+        if problems.is_empty() {
+            continue
+        }
+
+        invalid += 1;
+        out.push_str(&format!("line {}: {}\n", line, problems.iter().map(Problem::to_string).collect::<Vec<_>>().join("; ")));
+        for problem in problems {
+            *histogram.entry(problem.kind()).or_insert(0) += 1;
+        }
+    }
+
+    out.push_str(&format!("\n{} of {} passports invalid\n", invalid, total));
+
+    let mut histogram = histogram.into_iter().collect::<Vec<_>>();
+    histogram.sort_by(|(ka, ca), (kb, cb)| cb.cmp(ca).then(ka.cmp(kb)));
+    for (kind, count) in histogram {
+        out.push_str(&format!("{:>6} {}\n", count, kind));
+    }
+
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn parse<'a>(entry: &'a str) -> HashMap<&'a str, &'a str> {
-        let mut pass = HashMap::new();
-
-        entry.split_whitespace().for_each(|kv| {
-            let mut it = kv.split(":");
-            let k = it.next().unwrap();
-            let v = it.next().unwrap();
-            pass.insert(k, v);
-        });
-
-        pass
+    fn parse(entry: &str) -> Passport<'_> {
+        Passport::parse(1, entry).expect("valid passport")
     }
 
     #[test]
@@ -124,4 +455,111 @@ mod tests {
         assert!(valid_part2(&passport));
     }
 
+    #[test]
+    fn problems_are_reported() {
+        let input = "eyr:1972 cid:100 hcl:#18171d ecl:amb hgt:190 pid:186cm iyr:2018 byr:2003";
+        let passport = parse(input);
+        let messages = problems(&passport).iter().map(Problem::to_string).collect::<Vec<_>>();
+        assert_eq!(vec![
+            "byr 2003 above max 2002",
+            "eyr 1972 below min 2020",
+            "hgt 190 missing unit",
+            "pid 186cm bad format, expected nine digits",
+        ], messages);
+
+        let passport = parse("hgt:76in");
+        assert_eq!(Some(&Problem::Missing("byr")), problems(&passport).first());
+        assert_eq!(6, problems(&passport).len());
+    }
+
+    #[test]
+    fn entries_track_lines() {
+        let input = "a:1\nb:2\n\nc:3\n\n\nd:4 e:5\nf:6\n";
+        let entries = entries(input);
+        assert_eq!(vec![(1, "a:1\nb:2"), (4, "c:3"), (7, "d:4 e:5\nf:6\n")], entries);
+    }
+
+    #[test]
+    fn diagnostics_histogram() {
+        let passports = parse_batch("hgt:190 byr:2003\n\n\nhgt:59cm byr:1920\n\nbyr:1980\npid:1 byr:1981\n\nhgt\n\nx:1");
+        let report = diagnostics(&passports);
+        assert!(report.starts_with("line 1: byr 2003 above max 2002; missing iyr"));
+        assert!(report.contains("line 4: missing iyr; missing eyr; hgt 59cm below min 150"));
+        assert!(report.contains("line 7: duplicate key 'byr'\n"));
+        assert!(report.contains("line 9: expected key:value, got 'hgt'\n"));
+        assert!(report.contains("5 of 5 passports invalid"));
+        assert!(report.contains("     3 missing pid\n"));
+        assert!(report.contains("     1 hgt missing unit\n"));
+        assert!(report.contains("     1 duplicate key\n"));
+        assert!(report.contains("     1 missing colon\n"));
+    }
+
+    #[test]
+    fn parse_passport() {
+        let passport = Passport::parse(3, "byr:1980 url:http://x\nnote:a:b cid:1").unwrap();
+        assert_eq!(Passport {
+            line: 3,
+            byr: Some("1980"),
+            cid: Some("1"),
+            extra: vec![("url", "http://x"), ("note", "a:b")],
+            ..Default::default()
+        }, passport);
+        assert!(!valid_part1(&passport));
+
+        assert_eq!(Err(ParseError { line: 4, kind: ParseErrorKind::DuplicateKey("byr".to_string()) }), Passport::parse(3, "byr:1980\npid:1 byr:1981"));
+        assert_eq!(Err(ParseError { line: 3, kind: ParseErrorKind::DuplicateKey("x".to_string()) }), Passport::parse(3, "x:1 x:2"));
+        assert_eq!(Err(ParseError { line: 3, kind: ParseErrorKind::MissingColon("byr".to_string()) }), Passport::parse(3, "byr"));
+        assert_eq!(Err(ParseError { line: 3, kind: ParseErrorKind::EmptyKey(":1".to_string()) }), Passport::parse(3, ":1"));
+    }
+
+    #[test]
+    fn export() {
+        let passport = Passport::parse(1, "pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980 hcl:#623a2f note:\"a,b\"").unwrap();
+        assert_eq!(r##"{"line":1,"byr":"1980","iyr":"2012","eyr":"2030","hgt":"74in","hcl":"#623a2f","ecl":"grn","pid":"087499704","cid":null,"extra":{"note":"\"a,b\""},"valid":true}"##, passport.to_json());
+        assert_eq!(r##"1,1980,2012,2030,74in,#623a2f,grn,087499704,,"note:""a,b""",true"##, passport.to_csv());
+        assert_eq!("line,byr,iyr,eyr,hgt,hcl,ecl,pid,cid,extra,valid", csv_header());
+    }
+
+    #[test]
+    fn write_wraps_and_orders() {
+        let passport = parse("hgt:74in pid:087499704 ecl:grn\nnote:x byr:1980");
+        assert_eq!("byr:1980 hgt:74in ecl:grn pid:087499704 note:x", write_passport(&passport, &WriteOptions::default()));
+
+        let options = WriteOptions { width: Some(20), order: &["pid", "note"] };
+        assert_eq!("pid:087499704 note:x\nbyr:1980 hgt:74in\necl:grn", write_passport(&passport, &options));
+
+        let options = WriteOptions { width: Some(5), order: &[] };
+        assert_eq!("byr:1980\nhgt:74in\necl:grn\npid:087499704\nnote:x", write_passport(&passport, &options));
+
+        let options = WriteOptions { width: None, order: &["pid", "pid", "note", "byr", "note"] };
+        assert_eq!("pid:087499704 note:x byr:1980 hgt:74in ecl:grn", write_passport(&passport, &options));
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let input = "ecl:gry pid:860033327 eyr:2020 hcl:#fffffd
+byr:1937 iyr:2017 cid:147 hgt:183cm
+
+iyr:2013 ecl:amb cid:350 eyr:2023 pid:028048884
+hcl:#cfa07d byr:1929 url:http://a:b
+
+
+
+hcl:#ae17e1 iyr:2013
+eyr:2024
+ecl:brn pid:760753108 byr:1931
+hgt:179cm
+";
+        fn without_lines<'a>(passports: &[Passport<'a>]) -> Vec<Passport<'a>> {
+            passports.iter().map(|p| Passport { line: 0, ..p.clone() }).collect()
+        }
+        let parsed = parse_batch(input).into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+        for options in &[WriteOptions::default(), WriteOptions { width: Some(30), order: &["url", "pid"] }] {
+            let written = write_batch(&parsed, options);
+            let reparsed = parse_batch(&written).into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(without_lines(&parsed), without_lines(&reparsed));
+            assert_eq!(written, write_batch(&reparsed, options));
+        }
+    }
 }
@@ -3,16 +3,39 @@ use std::io::{self, BufRead, BufReader};
 
 
 fn main() -> io::Result<()> {
-    let input = std::env::args().skip(1).next().expect("Specify an input");
+    let mut args = std::env::args().skip(1);
+    let input = args.next().expect("Specify an input");
+    let mut layout = PlaneLayout::default();
+    let mut map = false;
+    for arg in args {
+        match arg.as_str() {
+            "map" => map = true,
+            other => layout = other.parse()?,
+        }
+    }
     println!("Input is {}", input);
     let f = File::open(input)?;
     let f = BufReader::new(f);
 
-    let mut seats = f.lines().filter_map(|l| l.and_then(|ll| ll.parse().map_err(|e| io::Error::new(io::ErrorKind::Other, e))).ok()).collect::<Vec<Seat>>();
+    let mut seats = f.lines().map(|l| l.and_then(|ll| layout.decode(&ll).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))).collect::<io::Result<Vec<Seat>>>()?;
     seats.sort();
 
-    println!("Part 01: {}", seats.iter().map(|s| s.id()).max().unwrap());
-    println!("Part 02: {:?}", seats.windows(2).find(|wnd| wnd[0].id() + 1 != wnd[1].id()).unwrap()[0].next().id());
+    let occupancy = Occupancy::new(layout, &seats);
+    println!("Part 01: {}", seats.iter().map(|s| layout.id(s)).max().unwrap());
+    let yours = occupancy.your_seat();
+    match yours {
+        Some(id) => println!("Part 02: {}", id),
+        None => println!("Part 02: no free seat with both neighbours taken"),
+    }
+
+    if map {
+        print!("{}", occupancy.render(yours));
+        let missing = occupancy.missing();
+        for (name, kind) in &[("front", MissingKind::Front), ("back", MissingKind::Back), ("gaps", MissingKind::Gap), ("interior", MissingKind::Interior)] {
+            let ids = missing.iter().filter(|m| m.kind == *kind).map(|m| m.id.to_string()).collect::<Vec<_>>();
+            println!("Missing {}: {}", name, if ids.is_empty() { "none".to_string() } else { ids.join(", ") });
+        }
+    }
 
     Ok(())
 }
@@ -24,44 +47,250 @@ struct Seat {
     column: u16,
 }
 
-impl Seat {
-    fn id(&self) -> u16 {
-        (self.row << 3) + self.column
+use std::fmt;
+use std::str::FromStr;
+
+/// Seat maps are kept densely, one entry per seat ID, so layouts are limited
+/// to about a million seats
+const MAX_SEAT_BITS: usize = 20;
+
+/// Describes how boarding passes map to seats: the number of bits (letters)
+/// used for the row and the column, and the letter pairs standing for the
+/// lower and upper half at each step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PlaneLayout {
+    row_bits: usize,
+    column_bits: usize,
+    row_letters: (char, char),
+    column_letters: (char, char),
+}
+
+impl Default for PlaneLayout {
+    fn default() -> Self {
+        PlaneLayout {
+            row_bits: 7,
+            column_bits: 3,
+            row_letters: ('F', 'B'),
+            column_letters: ('L', 'R'),
+        }
+    }
+}
+
+impl PlaneLayout {
+    fn pass_len(&self) -> usize {
+        self.row_bits + self.column_bits
     }
 
-    fn next(&self) -> Self {
-        // Broken but works for the subset
-        Self{row: self.row, column: self.column + 1}
+    fn decode(&self, pass: &str) -> Result<Seat, SeatError> {
+        let letters = pass.chars().collect::<Vec<_>>();
+        if letters.len() != self.pass_len() {
+            return Err(SeatError::WrongLength { expected: self.pass_len(), got: letters.len() })
+        }
+
+        let (r, s) = letters.split_at(self.row_bits);
+        Ok(Seat {
+            row: decode(r, self.row_letters, 0)?,
+            column: decode(s, self.column_letters, self.row_bits)?,
+        })
+    }
+
+    fn encode(&self, seat: &Seat) -> String {
+        let mut pass = encode(seat.row, self.row_bits, self.row_letters);
+        pass.push_str(&encode(seat.column, self.column_bits, self.column_letters));
+        pass
+    }
+
+    fn id(&self, seat: &Seat) -> u32 {
+        (u32::from(seat.row) << self.column_bits) + u32::from(seat.column)
+    }
+
+    fn seats(&self) -> u32 {
+        1 << (self.row_bits + self.column_bits)
+    }
+
+    fn seat(&self, id: u32) -> Seat {
+        Seat {
+            row: (id >> self.column_bits) as u16,
+            column: (id & ((1 << self.column_bits) - 1)) as u16,
+        }
     }
 }
 
-use std::str::FromStr;
-use std::num::ParseIntError;
-impl FromStr for Seat {
-    type Err = ParseIntError;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MissingKind {
+    /// Before the first taken seat, e.g. the missing front rows
+    Front,
+    /// After the last taken seat
+    Back,
+    /// Both neighbouring IDs are taken
+    Gap,
+    /// Between taken seats, but next to another missing one
+    Interior,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Missing {
+    id: u32,
+    kind: MissingKind,
+}
+
+/// Which seats of a plane are taken, indexed by seat ID
+struct Occupancy {
+    layout: PlaneLayout,
+    taken: Vec<bool>,
+}
+
+impl Occupancy {
+    fn new(layout: PlaneLayout, seats: &[Seat]) -> Self {
+        let mut taken = vec![false; layout.seats() as usize];
+        for seat in seats {
+            taken[layout.id(seat) as usize] = true;
+        }
+
+        Occupancy { layout, taken }
+    }
+
+    fn is_taken(&self, id: u32) -> bool {
+        self.taken.get(id as usize).copied().unwrap_or(false)
+    }
+
+    /// Every free seat of the plane, in ID order
+    fn missing(&self) -> Vec<Missing> {
+        let first = self.taken.iter().position(|t| *t).unwrap_or(self.taken.len()) as u32;
+        let last = self.taken.iter().rposition(|t| *t).unwrap_or(0) as u32;
+
+        (0..self.layout.seats()).filter(|id| !self.is_taken(*id)).map(|id| {
+            let kind = if id < first {
+                MissingKind::Front
+            } else if id > last {
+                MissingKind::Back
+            } else if self.is_taken(id - 1) && self.is_taken(id + 1) {
+                MissingKind::Gap
+            } else {
+                MissingKind::Interior
+            };
+
+            Missing { id, kind }
+        }).collect()
+    }
+
+    /// The only free seat whose neighbouring IDs are both taken
+    fn your_seat(&self) -> Option<u32> {
+        let mut gaps = self.missing().into_iter().filter(|m| m.kind == MissingKind::Gap);
+        match (gaps.next(), gaps.next()) {
+            (Some(gap), None) => Some(gap.id),
+            _ => None,
+        }
+    }
+
+    /// One line per row: `#` for taken seats, `.` for free ones and `@` for
+    /// `yours`.
+    fn render(&self, yours: Option<u32>) -> String {
+        let width = (self.layout.seats() >> self.layout.column_bits).to_string().len();
+        let mut out = String::new();
+        for id in 0..self.layout.seats() {
+            let seat = self.layout.seat(id);
+            if seat.column == 0 {
+                out.push_str(&format!("{:>width$} ", seat.row, width = width));
+            }
+
+            out.push(match (Some(id) == yours, self.is_taken(id)) {
+                (true, _) => '@',
+                (false, true) => '#',
+                (false, false) => '.',
+            });
+
+            if u32::from(seat.column) + 1 == 1 << self.layout.column_bits {
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+}
+
+/// Parses layouts written as `ROWSxCOLUMNS` bit counts, optionally followed by
+/// the row and column letter pairs, e.g. `7x3` or `7x3:FB:LR`.
+impl FromStr for PlaneLayout {
+    type Err = io::Error;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let (r, s) = input.split_at(7);
-        let mut seat = Seat {
-            row: 0,
-            column: 0,
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid layout '{}': {}", input, msg));
+        let mut parts = input.split(':');
+        let mut bits = parts.next().unwrap_or("").split('x').map(|b| b.parse::<usize>());
+        let mut layout = match (bits.next(), bits.next(), bits.next()) {
+            (Some(Ok(row_bits)), Some(Ok(column_bits)), None) => PlaneLayout { row_bits, column_bits, ..Default::default() },
+            _ => return Err(invalid("expected ROWSxCOLUMNS")),
         };
 
-        for c in r.chars() {
-            seat.row = seat.row << 1;
-            if c == 'B' {
-                seat.row += 1;
-            }
+        if layout.row_bits > 16 || layout.column_bits > 16 {
+            return Err(invalid("at most 16 bits for rows and columns"))
+        }
+        if layout.pass_len() > MAX_SEAT_BITS {
+            return Err(invalid(&format!("at most {} bits for rows and columns together", MAX_SEAT_BITS)))
         }
 
-        for c in s.chars() {
-            seat.column = seat.column << 1;
-            if c == 'R' {
-                seat.column += 1;
-            }
+        let mut letters = |default| match parts.next().map(|p| p.chars().collect::<Vec<_>>()).as_deref() {
+            None => Ok(default),
+            Some(&[zero, one]) if zero != one => Ok((zero, one)),
+            Some(_) => Err(invalid("expected two different letters")),
+        };
+        layout.row_letters = letters(layout.row_letters)?;
+        layout.column_letters = letters(layout.column_letters)?;
+
+        if parts.next().is_some() {
+            return Err(invalid("too many parts"))
         }
 
-        Ok(seat)
+        Ok(layout)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SeatError {
+    WrongLength { expected: usize, got: usize },
+    InvalidLetter { position: usize, letter: char },
+}
+
+impl fmt::Display for SeatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SeatError::WrongLength { expected, got } => write!(f, "boarding pass should have {} letters, got {}", expected, got),
+            SeatError::InvalidLetter { position, letter } => write!(f, "invalid letter '{}' at position {}", letter, position),
+        }
+    }
+}
+
+impl std::error::Error for SeatError {}
+
+/// Reads the letters as a binary number, `zero` and `one` being the only
+/// letters allowed. `offset` is only used to report the position of errors.
+fn decode(letters: &[char], (zero, one): (char, char), offset: usize) -> Result<u16, SeatError> {
+    letters.iter().enumerate().try_fold(0, |acc: u16, (i, &letter)| {
+        match letter {
+            c if c == zero => Ok(acc << 1),
+            c if c == one => Ok((acc << 1) + 1),
+            _ => Err(SeatError::InvalidLetter { position: offset + i, letter }),
+        }
+    })
+}
+
+fn encode(value: u16, bits: usize, (zero, one): (char, char)) -> String {
+    (0..bits).rev().map(|bit| if value & (1 << bit) == 0 { zero } else { one }).collect()
+}
+
+impl FromStr for Seat {
+    type Err = SeatError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        PlaneLayout::default().decode(input)
+    }
+}
+
+/// Formats the seat as its boarding pass on the standard plane
+impl fmt::Display for Seat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", PlaneLayout::default().encode(self))
     }
 }
 
@@ -77,6 +306,116 @@ mod tests {
 
     #[test]
     fn id_works() {
-        assert_eq!(Ok(357), "FBFBBFFRLR".parse::<Seat>().map(|s| s.id()));
+        assert_eq!(Ok(357), "FBFBBFFRLR".parse::<Seat>().map(|s| PlaneLayout::default().id(&s)));
+    }
+
+    #[test]
+    fn strict_parse() {
+        assert_eq!(Err(SeatError::WrongLength { expected: 10, got: 9 }), "FBFBBFFRL".parse::<Seat>());
+        assert_eq!(Err(SeatError::WrongLength { expected: 10, got: 11 }), "FBFBBFFRLRR".parse::<Seat>());
+        assert_eq!(Err(SeatError::InvalidLetter { position: 2, letter: 'X' }), "FBXBBFFRLR".parse::<Seat>());
+        assert_eq!(Err(SeatError::InvalidLetter { position: 7, letter: 'F' }), "FBFBBFFFLR".parse::<Seat>());
+        assert_eq!(Err(SeatError::InvalidLetter { position: 0, letter: 'é' }), "éBFBBFFRLR".parse::<Seat>());
+    }
+
+    #[test]
+    fn encode_works() {
+        assert_eq!("FBFBBFFRLR", Seat{row: 44, column: 5}.to_string());
+        assert_eq!("BBFFBBFRLL", Seat{row: 102, column: 4}.to_string());
+    }
+
+    #[test]
+    fn round_trip_all_seats() {
+        for row in 0..128 {
+            for column in 0..8 {
+                let seat = Seat{row, column};
+                let pass = seat.to_string();
+                assert_eq!(10, pass.len());
+                assert_eq!(Ok(Seat{row, column}), pass.parse());
+                assert_eq!(Ok(u32::from(row) * 8 + u32::from(column)), pass.parse::<Seat>().map(|s| PlaneLayout::default().id(&s)));
+            }
+        }
+    }
+
+    #[test]
+    fn layout_parse() {
+        assert_eq!(PlaneLayout::default(), "7x3".parse().unwrap());
+        assert_eq!(PlaneLayout::default(), "7x3:FB:LR".parse().unwrap());
+        assert_eq!(PlaneLayout { row_bits: 9, column_bits: 2, row_letters: ('A', 'Z'), column_letters: ('L', 'R') }, "9x2:AZ".parse().unwrap());
+        assert!("7".parse::<PlaneLayout>().is_err());
+        assert!("7x3:FF".parse::<PlaneLayout>().is_err());
+        assert!("7x3:FB:LR:XY".parse::<PlaneLayout>().is_err());
+        assert!("17x3".parse::<PlaneLayout>().is_err());
+        assert!("16x16".parse::<PlaneLayout>().is_err());
+        assert!("16x5".parse::<PlaneLayout>().is_err());
+        assert_eq!(20, "16x4".parse::<PlaneLayout>().unwrap().pass_len());
+    }
+
+    #[test]
+    fn other_layouts() {
+        let layout: PlaneLayout = "4x2:01:ab".parse().unwrap();
+        assert_eq!(Ok(Seat{row: 5, column: 2}), layout.decode("0101ba"));
+        assert_eq!(Err(SeatError::WrongLength { expected: 6, got: 10 }), layout.decode("FBFBBFFRLR"));
+        assert_eq!(Err(SeatError::InvalidLetter { position: 4, letter: 'R' }), layout.decode("0101Ra"));
+        assert_eq!(22, layout.id(&Seat{row: 5, column: 2}));
+
+        for row in 0..16 {
+            for column in 0..4 {
+                let seat = Seat{row, column};
+                assert_eq!(Ok(seat), layout.decode(&layout.encode(&Seat{row, column})));
+            }
+        }
+    }
+
+    fn seats(seats: &[(u16, u16)]) -> Vec<Seat> {
+        seats.iter().map(|&(row, column)| Seat{row, column}).collect()
+    }
+
+    #[test]
+    fn missing_seat_works() {
+        let layout: PlaneLayout = "4x2".parse().unwrap();
+        let seats = seats(&[(2, 2), (2, 3), (3, 1), (3, 2)]);
+        assert_eq!(Some(12), Occupancy::new(layout, &seats).your_seat());
+        assert_eq!(None, Occupancy::new(layout, &seats[..2]).your_seat());
+    }
+
+    #[test]
+    fn missing_seat_wraps_rows() {
+        let layout: PlaneLayout = "3x2".parse().unwrap();
+        let occupancy = Occupancy::new(layout, &seats(&[(1, 2), (2, 0), (2, 1)]));
+        assert_eq!(Some(7), occupancy.your_seat());
+        assert_eq!(Seat{row: 1, column: 3}, layout.seat(7));
+    }
+
+    #[test]
+    fn missing_seats_are_classified() {
+        let layout: PlaneLayout = "2x2".parse().unwrap();
+        let occupancy = Occupancy::new(layout, &seats(&[(1, 0), (1, 2), (2, 1), (2, 2)]));
+        let missing = occupancy.missing().into_iter().map(|m| (m.id, m.kind)).collect::<Vec<_>>();
+        assert_eq!(vec![
+            (0, MissingKind::Front),
+            (1, MissingKind::Front),
+            (2, MissingKind::Front),
+            (3, MissingKind::Front),
+            (5, MissingKind::Gap),
+            (7, MissingKind::Interior),
+            (8, MissingKind::Interior),
+            (11, MissingKind::Back),
+            (12, MissingKind::Back),
+            (13, MissingKind::Back),
+            (14, MissingKind::Back),
+            (15, MissingKind::Back),
+        ], missing);
+        assert_eq!(Some(5), occupancy.your_seat());
+
+        let occupancy = Occupancy::new(layout, &seats(&[(1, 0), (1, 2), (2, 0), (2, 2)]));
+        assert_eq!(None, occupancy.your_seat());
+    }
+
+    #[test]
+    fn render_works() {
+        let layout: PlaneLayout = "2x2".parse().unwrap();
+        let occupancy = Occupancy::new(layout, &seats(&[(1, 0), (1, 2), (1, 3), (2, 0)]));
+        assert_eq!("0 ....\n1 #@##\n2 #...\n3 ....\n", occupancy.render(occupancy.your_seat()));
     }
 }
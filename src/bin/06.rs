@@ -1,37 +1,474 @@
 use std::env;
 use std::fs::File;
 use std::io::{self, Read};
-//use regex::Regex;
 
-use std::collections::HashSet;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::ops::{BitAnd, BitOr, BitXor, Sub};
+use std::str::FromStr;
 
 fn main() -> io::Result<()> {
-    let path = env::args().skip(1).next().expect("Specify an input file");
+    let mut args = env::args().skip(1);
+    let path = args.next().expect("Specify an input file");
+    let mode = args.next();
     let mut file = File::open(path)?;
     let mut s = String::new();
     file.read_to_string(&mut s)?;
 
-    let groups = s.split("\n\n").collect::<Vec<_>>();
-    let answers_part1 = groups.iter().map(|group| group.split_whitespace().fold(HashSet::new(), |mut h, a| {
-        a.chars().for_each(|c| { h.insert(c); });
-        h
-    })).collect::<Vec<HashSet<char>>>();
-
-    println!("Part 01: {}", answers_part1.iter().map(HashSet::len).sum::<usize>());
-
-    let answer_part2: usize = groups.iter().map(|group| {
-        let indiv = group.split_whitespace().collect::<Vec<_>>();
-        let h = indiv.iter().fold(HashMap::new(), |mut h, a| {
-            a.chars().for_each(|c| {
-                let entry = h.entry(c).or_insert(0);
-                *entry += 1;
-            });
-            h
-        });
-        h.iter().filter(|(_k, v)| **v == indiv.len()).count()
-    }).sum();
-    println!("Part 02: {}", answer_part2);
+    let groups = s.split("\n\n").map(str::parse).collect::<io::Result<Vec<Group>>>()?;
+
+    if mode.as_deref() == Some("stats") {
+        let stats = Stats::new(&groups);
+        match args.next().as_deref() {
+            None | Some("table") => print!("{}", stats.table()),
+            Some("json") => println!("{}", stats.json()),
+            Some(other) => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown format '{}', expected table or json", other))),
+        }
+        return Ok(())
+    }
+
+    let expr = mode.map(|e| e.parse::<Expr>()).transpose()?;
+
+    println!("Part 01: {}", groups.iter().map(|g| g.eval(&Expr::Anyone).len()).sum::<usize>());
+    println!("Part 02: {}", groups.iter().map(|g| g.eval(&Expr::Everyone).len()).sum::<usize>());
+
+    if let Some(expr) = expr {
+        println!("{:?}: {}", expr, groups.iter().map(|g| g.eval(&expr).len()).sum::<usize>());
+    }
 
     Ok(())
 }
+
+/// A set of questions `a` to `z`, one bit per question
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Answers(u32);
+
+impl Answers {
+    fn all() -> Self {
+        Answers((1 << 26) - 1)
+    }
+
+    fn from_question(question: char) -> Option<Self> {
+        if question.is_ascii_lowercase() {
+            Some(Answers(1 << (question as u32 - 'a' as u32)))
+        } else {
+            None
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        Answers(self.0 | other.0)
+    }
+
+    fn intersection(self, other: Self) -> Self {
+        Answers(self.0 & other.0)
+    }
+
+    fn difference(self, other: Self) -> Self {
+        Answers(self.0 & !other.0)
+    }
+
+    fn symmetric_difference(self, other: Self) -> Self {
+        Answers(self.0 ^ other.0)
+    }
+
+    fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    fn contains(self, question: char) -> bool {
+        Answers::from_question(question).map(|q| self.intersection(q) == q).unwrap_or(false)
+    }
+
+    fn questions(self) -> impl Iterator<Item = char> {
+        ('a'..='z').filter(move |q| self.contains(*q))
+    }
+}
+
+impl FromStr for Answers {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        input.chars().try_fold(Answers::default(), |answers, c| {
+            Answers::from_question(c).map(|q| answers | q).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid question '{}'", c)))
+        })
+    }
+}
+
+impl BitOr for Answers {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+impl BitAnd for Answers {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        self.intersection(other)
+    }
+}
+
+impl BitXor for Answers {
+    type Output = Self;
+
+    fn bitxor(self, other: Self) -> Self {
+        self.symmetric_difference(other)
+    }
+}
+
+impl Sub for Answers {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.difference(other)
+    }
+}
+
+/// A set expression evaluated over the members of a group
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    /// The answers of a single member
+    Member(usize),
+    /// Answered by anyone in the group
+    Anyone,
+    /// Answered by everyone in the group
+    Everyone,
+    /// Answered by at least that many members
+    AtLeast(usize),
+    /// Answered by exactly that many members
+    Exactly(usize),
+    Union(Box<Expr>, Box<Expr>),
+    Intersection(Box<Expr>, Box<Expr>),
+    Difference(Box<Expr>, Box<Expr>),
+    SymmetricDifference(Box<Expr>, Box<Expr>),
+}
+
+/// Parses whitespace separated terms combined left to right, e.g.
+/// `atleast:2 - everyone` or `member:0 ^ member:1 | exactly:1`. Terms are
+/// `anyone`, `everyone`, `member:N`, `atleast:N` and `exactly:N`, operators
+/// `|`, `&`, `-` and `^`. Parentheses group a sub-expression, as in
+/// `(member:0 | member:1) & (member:2 | member:3)`.
+impl FromStr for Expr {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        fn invalid(msg: String) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        }
+
+        fn term(t: &str) -> io::Result<Expr> {
+            let (name, n) = match t.split_once(':') {
+                Some((name, n)) => (name, Some(n.parse::<usize>().map_err(|_| invalid(format!("invalid number in '{}'", t)))?)),
+                None => (t, None),
+            };
+            match (name, n) {
+                ("anyone", None) => Ok(Expr::Anyone),
+                ("everyone", None) => Ok(Expr::Everyone),
+                ("member", Some(n)) => Ok(Expr::Member(n)),
+                ("atleast", Some(n)) => Ok(Expr::AtLeast(n)),
+                ("exactly", Some(n)) => Ok(Expr::Exactly(n)),
+                _ => Err(invalid(format!("unknown term '{}'", t))),
+            }
+        }
+
+        fn operand<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> io::Result<Expr> {
+            match tokens.next() {
+                Some("(") => {
+                    let expr = expr(tokens)?;
+                    match tokens.next() {
+                        Some(")") => Ok(expr),
+                        _ => Err(invalid("unclosed '('".to_string())),
+                    }
+                }
+                Some(")") => Err(invalid("unexpected ')'".to_string())),
+                Some(t) => term(t),
+                None => Err(invalid("expression ends with an operator".to_string())),
+            }
+        }
+
+        /// Operands combined left to right, up to a `)` or the end
+        fn expr<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> io::Result<Expr> {
+            let mut expr = operand(tokens)?;
+            while let Some(op) = tokens.next_if(|t| *t != ")") {
+                let (a, b) = (Box::new(expr), Box::new(operand(tokens)?));
+                expr = match op {
+                    "|" => Expr::Union(a, b),
+                    "&" => Expr::Intersection(a, b),
+                    "-" => Expr::Difference(a, b),
+                    "^" => Expr::SymmetricDifference(a, b),
+                    _ => return Err(invalid(format!("unknown operator '{}'", op))),
+                };
+            }
+            Ok(expr)
+        }
+
+        let spaced = input.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens = spaced.split_whitespace().peekable();
+        let expr = expr(&mut tokens)?;
+        match tokens.next() {
+            Some(_) => Err(invalid("unmatched ')'".to_string())),
+            None => Ok(expr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Group {
+    members: Vec<Answers>,
+}
+
+impl Group {
+    /// How many members answered each question, `a` first
+    fn counts(&self) -> [usize; 26] {
+        let mut counts = [0; 26];
+        for member in &self.members {
+            for q in member.questions() {
+                counts[q as usize - 'a' as usize] += 1;
+            }
+        }
+        counts
+    }
+
+    fn answered_by(&self, predicate: impl Fn(usize) -> bool) -> Answers {
+        ('a'..='z').zip(self.counts().iter())
+            .filter(|(_, count)| predicate(**count))
+            .filter_map(|(q, _)| Answers::from_question(q))
+            .fold(Answers::default(), Answers::union)
+    }
+
+    fn eval(&self, expr: &Expr) -> Answers {
+        match expr {
+            Expr::Member(i) => self.members.get(*i).copied().unwrap_or_default(),
+            Expr::Anyone => self.members.iter().fold(Answers::default(), |acc, m| acc | *m),
+            Expr::Everyone if self.members.is_empty() => Answers::default(),
+            Expr::Everyone => self.members.iter().fold(Answers::all(), |acc, m| acc & *m),
+            Expr::AtLeast(k) => self.answered_by(|count| count >= *k && count > 0),
+            Expr::Exactly(k) => self.answered_by(|count| count == *k),
+            Expr::Union(a, b) => self.eval(a) | self.eval(b),
+            Expr::Intersection(a, b) => self.eval(a) & self.eval(b),
+            Expr::Difference(a, b) => self.eval(a) - self.eval(b),
+            Expr::SymmetricDifference(a, b) => self.eval(a) ^ self.eval(b),
+        }
+    }
+}
+
+impl FromStr for Group {
+    type Err = io::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let members = input.split_whitespace().map(str::parse).collect::<io::Result<Vec<Answers>>>()?;
+        Ok(Group { members })
+    }
+}
+
+/// Survey statistics across all groups
+#[derive(Debug, Clone, PartialEq)]
+struct Stats {
+    people: usize,
+    /// How many people answered each question, `a` first
+    frequencies: [usize; 26],
+    /// Number of groups of each size
+    sizes: BTreeMap<usize, usize>,
+    /// For each group, the share of its questions that everyone answered, or
+    /// `None` when nobody answered anything
+    agreement: Vec<Option<f64>>,
+}
+
+impl Stats {
+    fn new(groups: &[Group]) -> Self {
+        let mut stats = Stats { people: 0, frequencies: [0; 26], sizes: BTreeMap::new(), agreement: Vec::with_capacity(groups.len()) };
+
+        for group in groups {
+            stats.people += group.members.len();
+            *stats.sizes.entry(group.members.len()).or_insert(0) += 1;
+            for (total, count) in stats.frequencies.iter_mut().zip(group.counts().iter()) {
+                *total += count;
+            }
+
+            let anyone = group.eval(&Expr::Anyone).len();
+            let everyone = group.eval(&Expr::Everyone).len();
+            stats.agreement.push(if anyone == 0 { None } else { Some(everyone as f64 / anyone as f64) });
+        }
+
+        stats
+    }
+
+    fn questions(&self) -> impl Iterator<Item = (char, usize)> + '_ {
+        ('a'..='z').zip(self.frequencies.iter().copied())
+    }
+
+    /// The questions answered by the most people, along with that count, or
+    /// none when nothing was answered
+    fn most_common(&self) -> (Vec<char>, usize) {
+        let max = self.frequencies.iter().copied().max().unwrap_or(0);
+        (self.questions().filter(|(_, n)| *n == max && *n > 0).map(|(q, _)| q).collect(), max)
+    }
+
+    /// The questions answered by the fewest people, ignoring unanswered ones,
+    /// or none when nothing was answered
+    fn least_common(&self) -> (Vec<char>, usize) {
+        let min = self.frequencies.iter().copied().filter(|n| *n > 0).min().unwrap_or(0);
+        (self.questions().filter(|(_, n)| *n == min && *n > 0).map(|(q, _)| q).collect(), min)
+    }
+
+    fn mean_agreement(&self) -> Option<f64> {
+        let ratios = self.agreement.iter().flatten().collect::<Vec<_>>();
+        if ratios.is_empty() {
+            None
+        } else {
+            Some(ratios.iter().copied().sum::<f64>() / ratios.len() as f64)
+        }
+    }
+
+    fn table(&self) -> String {
+        let percent = |n: usize| if self.people == 0 { 0.0 } else { 100.0 * n as f64 / self.people as f64 };
+        let ratio = |r: &Option<f64>| r.map(|r| format!("{:.2}", r)).unwrap_or_else(|| "-".to_string());
+        let letters = |qs: &[char]| qs.iter().map(char::to_string).collect::<Vec<_>>().join(" ");
+
+        let mut out = format!("{} groups, {} people\n\nQuestion  People  Share\n", self.agreement.len(), self.people);
+        for (q, n) in self.questions() {
+            out.push_str(&format!("{:>8}  {:>6}  {:>5.1}%\n", q, n, percent(n)));
+        }
+
+        out.push_str("\nGroup size  Groups\n");
+        for (size, n) in &self.sizes {
+            out.push_str(&format!("{:>10}  {:>6}\n", size, n));
+        }
+
+        let (most, most_n) = self.most_common();
+        let (least, least_n) = self.least_common();
+        out.push_str(&format!("\nMost common: {} ({})\nLeast common: {} ({})\n", letters(&most), most_n, letters(&least), least_n));
+
+        out.push_str(&format!("\nMean agreement: {}\nGroup  Agreement\n", ratio(&self.mean_agreement())));
+        for (i, r) in self.agreement.iter().enumerate() {
+            out.push_str(&format!("{:>5}  {:>9}\n", i + 1, ratio(r)));
+        }
+
+        out
+    }
+
+    fn json(&self) -> String {
+        let number = |r: &Option<f64>| r.map(|r| format!("{:.4}", r)).unwrap_or_else(|| "null".to_string());
+        let letters = |qs: &[char]| qs.iter().map(|q| format!("\"{}\"", q)).collect::<Vec<_>>().join(",");
+
+        let frequencies = self.questions().map(|(q, n)| format!("\"{}\":{}", q, n)).collect::<Vec<_>>();
+        let sizes = self.sizes.iter().map(|(size, n)| format!("\"{}\":{}", size, n)).collect::<Vec<_>>();
+        let agreement = self.agreement.iter().map(number).collect::<Vec<_>>();
+        let (most, most_n) = self.most_common();
+        let (least, least_n) = self.least_common();
+
+        format!(
+            "{{\"groups\":{},\"people\":{},\"frequencies\":{{{}}},\"group_sizes\":{{{}}},\"most_common\":{{\"questions\":[{}],\"people\":{}}},\"least_common\":{{\"questions\":[{}],\"people\":{}}},\"mean_agreement\":{},\"agreement\":[{}]}}",
+            self.agreement.len(), self.people, frequencies.join(","), sizes.join(","),
+            letters(&most), most_n, letters(&least), least_n,
+            number(&self.mean_agreement()), agreement.join(","),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answers(s: &str) -> Answers {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn answers_set_algebra() {
+        let a = answers("abcx");
+        let b = answers("abcy");
+        assert_eq!(answers("abcxy"), a | b);
+        assert_eq!(answers("abc"), a & b);
+        assert_eq!(answers("x"), a - b);
+        assert_eq!(answers("xy"), a ^ b);
+        assert_eq!(4, a.len());
+        assert_eq!(26, Answers::all().len());
+        assert_eq!(vec!['a', 'b', 'c', 'x'], a.questions().collect::<Vec<_>>());
+        assert!("abC".parse::<Answers>().is_err());
+    }
+
+    #[test]
+    fn example_groups() {
+        let input = "abc\n\na\nb\nc\n\nab\nac\n\na\na\na\na\n\nb";
+        let groups = input.split("\n\n").map(|g| g.parse::<Group>().unwrap()).collect::<Vec<_>>();
+        assert_eq!(11, groups.iter().map(|g| g.eval(&Expr::Anyone).len()).sum::<usize>());
+        assert_eq!(6, groups.iter().map(|g| g.eval(&Expr::Everyone).len()).sum::<usize>());
+    }
+
+    #[test]
+    fn expressions() {
+        let group: Group = "abc\nabd\nae\nf".parse().unwrap();
+        assert_eq!(answers("ab"), group.eval(&Expr::AtLeast(2)));
+        assert_eq!(answers("cdef"), group.eval(&Expr::Exactly(1)));
+        assert_eq!(answers("a"), group.eval(&Expr::Exactly(3)));
+        assert_eq!(group.eval(&Expr::Anyone), group.eval(&Expr::AtLeast(0)));
+        assert_eq!(answers("ae"), group.eval(&Expr::Difference(Box::new(Expr::Member(2)), Box::new(Expr::Member(3)))));
+        assert_eq!(answers("cd"), group.eval(&Expr::SymmetricDifference(Box::new(Expr::Member(0)), Box::new(Expr::Member(1)))));
+        assert_eq!(answers("ab"), group.eval(&Expr::Intersection(
+            Box::new(Expr::Union(Box::new(Expr::Member(0)), Box::new(Expr::Member(3)))),
+            Box::new(Expr::AtLeast(2)),
+        )));
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let group: Group = "abc\nabd\nae\nf".parse().unwrap();
+        let eval = |e: &str| group.eval(&e.parse().unwrap());
+        assert_eq!(answers("b"), eval("atleast:2 - member:2"));
+        assert_eq!(answers("cdef"), eval("member:0 ^ member:1 | exactly:1"));
+        assert_eq!(answers(""), eval("everyone"));
+        assert_eq!(answers("abcdef"), eval("anyone & atleast:1"));
+        assert!("anyone |".parse::<Expr>().is_err());
+        assert!("anyone + everyone".parse::<Expr>().is_err());
+        assert!("member".parse::<Expr>().is_err());
+        assert!("exactly:x".parse::<Expr>().is_err());
+
+        assert_eq!(answers("ab"), eval("(member:0 | member:3) & atleast:2"));
+        assert_eq!(answers("a"), eval("(member:0 | member:1) & (member:2 | member:3)"));
+        assert_eq!(answers("cdef"), eval("exactly:1 | (member:0 ^ member:1)"));
+        assert_eq!(answers("c"), eval("member:0 - ((member:1))"));
+        assert_eq!(answers("a"), eval("anyone - (anyone - member:2) - member:3 - (member:1 ^ member:2)"));
+        assert!("(anyone | everyone".parse::<Expr>().is_err());
+        assert!("anyone | everyone)".parse::<Expr>().is_err());
+        assert!("anyone ( everyone )".parse::<Expr>().is_err());
+        assert!("()".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn stats_works() {
+        let input = "abc\n\na\nb\nc\n\nab\nac\n\na\na\na\na\n\nb";
+        let groups = input.split("\n\n").map(|g| g.parse::<Group>().unwrap()).collect::<Vec<_>>();
+        let stats = Stats::new(&groups);
+
+        assert_eq!(11, stats.people);
+        assert_eq!([8, 4, 3], stats.frequencies[..3]);
+        assert_eq!(vec![(1, 2), (2, 1), (3, 1), (4, 1)], stats.sizes.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>());
+        assert_eq!(vec![Some(1.0), Some(0.0), Some(1.0 / 3.0), Some(1.0), Some(1.0)], stats.agreement);
+        assert_eq!((vec!['a'], 8), stats.most_common());
+        assert_eq!((vec!['c'], 3), stats.least_common());
+
+        let table = stats.table();
+        assert!(table.starts_with("5 groups, 11 people\n"));
+        assert!(table.contains("       a       8   72.7%\n"));
+        assert!(table.contains("Most common: a (8)\nLeast common: c (3)\n"));
+        assert!(table.contains("    3       0.33\n"));
+
+        let json = stats.json();
+        assert!(json.starts_with(r#"{"groups":5,"people":11,"frequencies":{"a":8,"b":4,"c":3,"d":0,"#));
+        assert!(json.contains(r#""group_sizes":{"1":2,"2":1,"3":1,"4":1}"#));
+        assert!(json.contains(r#""least_common":{"questions":["c"],"people":3}"#));
+        assert!(json.ends_with(r#""agreement":[1.0000,0.0000,0.3333,1.0000,1.0000]}"#));
+    }
+
+    #[test]
+    fn stats_without_answers() {
+        let stats = Stats::new(&["".parse().unwrap()]);
+        assert_eq!((vec![], 0), stats.most_common());
+        assert_eq!((vec![], 0), stats.least_common());
+        assert!(stats.json().contains(r#""least_common":{"questions":[],"people":0}"#));
+    }
+}
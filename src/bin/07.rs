@@ -1,139 +1,606 @@
 use std::fs::File;
 use std::io::{self, Read};
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
 fn main() -> io::Result<()> {
-    let input = std::env::args().skip(1).next().expect("Specify an input");
-    println!("Input is {}", input);
-    let mut f = File::open(input)?;
+    let mut args = std::env::args().skip(1);
+    let input = args.next().expect("Specify an input");
+    let query = args.collect::<Vec<_>>();
+    let mut f = File::open(&input)?;
     let mut s = String::new();
     f.read_to_string(&mut s)?;
 
-    println!("Part 01: {}", part1(&s));
-    println!("Part 02: {}", part2(&s));
+    let lines = parse::lines(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let graph = BagGraph::new(lines);
+
+    if !query.is_empty() {
+        let answer = run_query(&graph, &query).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        print!("{}", answer);
+        return Ok(())
+    }
+
+    println!("Input is {}", input);
+    println!("Part 01: {}", part1(&graph));
+    match part2(&graph) {
+        Ok(count) => println!("Part 02: {}", count),
+        Err(e) => println!("Part 02: {}", graph.describe(&e)),
+    }
     Ok(())
 }
 
-fn part2(input: &str) -> usize {
-    let (_, lines) = parse::lines(input).expect("valid input");
-    let mut rules = HashMap::new();
-    for (container, contained) in lines {
-        rules.insert(container, contained);
+/// Answers `contains <bag>`, `inside <bag>`, `direct <bag>`,
+/// `path <from> <to>`, `validate` and `export <dot|mermaid> [options]`.
+/// Single bag queries accept the colour as one or several arguments, `path`
+/// and export options need each colour as a single (quoted) argument.
+fn run_query(graph: &BagGraph, query: &[String]) -> Result<String, String> {
+    let (command, rest) = query.split_first().ok_or("empty query")?;
+    let bag = || graph.lookup(&rest.join(" "));
+
+    match command.as_str() {
+        "contains" => {
+            let id = bag()?;
+            Ok(format!("{} colours can eventually contain {} bags\n", graph.ancestors(id).len(), graph.name(id)))
+        },
+        "inside" => {
+            let id = bag()?;
+            let count = graph.bags_inside(id).map_err(|e| graph.describe(&e))?;
+            Ok(format!("{} bags must contain {} bags of {} colours\n", graph.name(id), count, graph.descendants(id).len()))
+        },
+        "direct" => {
+            let id = bag()?;
+            let list = |edges: &[(usize, usize)]| {
+                if edges.is_empty() {
+                    "  nothing\n".to_string()
+                } else {
+                    edges.iter().map(|(count, other)| format!("  {} {}\n", count, graph.name(*other))).collect()
+                }
+            };
+            Ok(format!("{} bags directly contain:\n{}{} bags are directly inside:\n{}", graph.name(id), list(&graph.contains[id]), graph.name(id), list(&graph.contained_in[id])))
+        },
+        "path" => {
+            let (from, to) = match rest {
+                [from, to] => (graph.lookup(from)?, graph.lookup(to)?),
+                _ => return Err("usage: path <from> <to>, quoting each colour".to_string()),
+            };
+            match graph.path(from, to) {
+                None => Ok(format!("{} bags cannot contain {} bags\n", graph.name(from), graph.name(to))),
+                Some(path) => {
+                    let mut out = graph.name(from);
+                    for (count, id) in &path {
+                        out.push_str(&format!(" -> {} {}", count, graph.name(*id)));
+                    }
+                    let bags = path.iter().try_fold(1usize, |total, (count, _)| total.checked_mul(*count))
+                        .ok_or_else(|| graph.describe(&RuleError::Overflow(from)))?;
+                    Ok(format!("{} ({} bags)\n", out, bags))
+                },
+            }
+        },
+        "export" => {
+            let (format, options) = rest.split_first().ok_or("usage: export <dot|mermaid> [--from <bag>] [--to <bag>] [--highlight <bag>] [--output <file>]")?;
+            let mut export = ExportOptions {
+                format: match format.as_str() {
+                    "dot" => ExportFormat::Dot,
+                    "mermaid" => ExportFormat::Mermaid,
+                    other => return Err(format!("unknown export format '{}', expected dot or mermaid", other)),
+                },
+                from: None,
+                to: None,
+                highlight: None,
+            };
+            let mut output = None;
+            for option in options.chunks(2) {
+                match option {
+                    [name, value] if name == "--from" => export.from = Some(graph.lookup(value)?),
+                    [name, value] if name == "--to" => export.to = Some(graph.lookup(value)?),
+                    [name, value] if name == "--highlight" => export.highlight = Some(graph.lookup(value)?),
+                    [name, value] if name == "--output" => output = Some(value),
+                    _ => return Err(format!("invalid export option '{}'", option.join(" "))),
+                }
+            }
+
+            let text = graph.export(&export);
+            match output {
+                Some(path) => {
+                    std::fs::write(path, &text).map_err(|e| format!("could not write {}: {}", path, e))?;
+                    Ok(format!("wrote {}\n", path))
+                },
+                None => Ok(text),
+            }
+        },
+        "validate" => {
+            let errors = graph.validate();
+            if errors.is_empty() {
+                Ok("no problems found\n".to_string())
+            } else {
+                Ok(errors.iter().map(|e| format!("{}\n", graph.describe(e))).collect())
+            }
+        },
+        other => Err(format!("unknown query '{}', expected contains, inside, direct, path, validate or export", other)),
+    }
+}
+
+/// Number of single character insertions, deletions or substitutions
+/// needed to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
     }
 
+    previous[b.len()]
+}
+
+/// A colour name, one or more words separated by single spaces
+type Color<'a> = &'a str;
+
+const SHINY_GOLD: Color<'static> = "shiny gold";
+
+fn part1(graph: &BagGraph) -> usize {
+    graph.id(&SHINY_GOLD).map(|id| graph.ancestors(id).len()).unwrap_or(0)
+}
+
+fn part2(graph: &BagGraph) -> Result<usize, RuleError> {
+    match graph.id(&SHINY_GOLD) {
+        Some(id) => graph.bags_inside(id),
+        None => Err(RuleError::Unknown(SHINY_GOLD.to_string())),
+    }
+}
 
-    weight(&rules, &("shiny", "gold")) - 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RuleError {
+    /// Bags containing each other, the first bag being repeated at the end
+    Cycle(Vec<usize>),
+    /// A bag that other bags contain but that has no rule of its own
+    Undefined { bag: usize, inside: Vec<usize> },
+    /// A bag with more than one rule
+    DuplicateRule { bag: usize, rules: usize },
+    /// A bag whose rules list another bag more than once with different counts
+    ContradictoryCount { container: usize, contained: usize, counts: Vec<usize> },
+    /// A colour that no rule mentions
+    Unknown(String),
+    /// Too many bags inside that bag to be counted
+    Overflow(usize),
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    Dot,
+    Mermaid,
 }
 
-fn weight(rules: &HashMap<(&str, &str), Vec<(usize, (&str, &str))>>, bag: &(&str, &str)) -> usize {
-    let contained = rules.get(bag).unwrap();
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExportOptions {
+    format: ExportFormat,
+    /// Only export this bag and the bags it eventually contains
+    from: Option<usize>,
+    /// Only export this bag and the bags that can eventually contain it
+    to: Option<usize>,
+    /// Bag to highlight, `from` or else `to` by default
+    highlight: Option<usize>,
+}
 
-    contained.iter().fold(1, |sum, (w, b)| sum + (w * weight(rules, b)))
+/// The bag rules as a graph. Colours are interned to consecutive IDs, and
+/// each edge is stored in both directions along with its count.
+struct BagGraph<'a> {
+    colors: Vec<Color<'a>>,
+    ids: HashMap<Color<'a>, usize>,
+    /// `(count, id)` of the bags directly inside each bag
+    contains: Vec<Vec<(usize, usize)>>,
+    /// `(count, id)` of the bags each bag is directly inside of
+    contained_in: Vec<Vec<(usize, usize)>>,
+    /// How many rules define each bag
+    rules: Vec<usize>,
 }
 
-fn part1(input: &str) -> usize {
-    let (_, lines) = parse::lines(input).expect("valid input");
+impl<'a> BagGraph<'a> {
+    fn new(rules: Vec<(Color<'a>, Vec<(usize, Color<'a>)>)>) -> Self {
+        let mut graph = BagGraph { colors: Vec::new(), ids: HashMap::new(), contains: Vec::new(), contained_in: Vec::new(), rules: Vec::new() };
+
+        for (container, contained) in rules {
+            let from = graph.intern(container);
+            graph.rules[from] += 1;
+            for (count, color) in contained {
+                let to = graph.intern(color);
+                graph.contains[from].push((count, to));
+                graph.contained_in[to].push((count, from));
+            }
+        }
+
+        graph
+    }
+
+    fn intern(&mut self, color: Color<'a>) -> usize {
+        if let Some(id) = self.ids.get(&color) {
+            return *id
+        }
+
+        let id = self.colors.len();
+        self.colors.push(color);
+        self.ids.insert(color, id);
+        self.contains.push(Vec::new());
+        self.contained_in.push(Vec::new());
+        self.rules.push(0);
+        id
+    }
+
+    fn id(&self, color: &Color) -> Option<usize> {
+        self.ids.get(color).copied()
+    }
 
-    let mut outer_bags = HashSet::with_capacity(lines.len());
-    let mut inverted = HashMap::new();
-    for (container, contained) in lines {
-        outer_bags.insert(container);
+    fn name(&self, id: usize) -> String {
+        self.colors[id].to_string()
+    }
 
-        for (_count, containee) in contained {
-            let entry = inverted.entry(containee).or_insert_with(HashSet::new);
-            (*entry).insert(container);
+    /// Finds a colour by name, suggesting the closest known one if there is
+    /// no such colour
+    fn lookup(&self, name: &str) -> Result<usize, String> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(id) = self.id(&name.as_str()) {
+            return Ok(id)
+        }
 
+        match (0..self.len()).min_by_key(|id| edit_distance(&name, &self.name(*id))) {
+            Some(closest) => Err(format!("unknown colour '{}', did you mean '{}'?", name, self.name(closest))),
+            None => Err(format!("unknown colour '{}'", name)),
         }
     }
 
-    let mut solution = HashSet::new();
-    let mut visited = HashSet::new();
-    visited.insert(("shiny", "gold"));
-    let mut to_visit = inverted.get(&("shiny", "gold")).unwrap().iter().map(|x| *x).collect::<VecDeque<(&str, &str)>>();
-    while let Some(x) = to_visit.pop_front() {
-        if visited.contains(&x) {
-            continue
+    fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// Every bag reachable by following `edges` from `id`, excluding `id`
+    /// itself unless it is part of a cycle
+    fn reachable(&self, id: usize, edges: &[Vec<(usize, usize)>]) -> HashSet<usize> {
+        let mut visited = HashSet::new();
+        let mut to_visit = edges[id].iter().map(|(_, other)| *other).collect::<VecDeque<_>>();
+        while let Some(x) = to_visit.pop_front() {
+            if visited.insert(x) {
+                to_visit.extend(edges[x].iter().map(|(_, other)| *other));
+            }
         }
 
-        if outer_bags.contains(&x) {
-            solution.insert(x);
+        visited
+    }
+
+    /// Bags that can eventually contain `id`
+    fn ancestors(&self, id: usize) -> HashSet<usize> {
+        self.reachable(id, &self.contained_in)
+    }
+
+    /// Bags that `id` eventually contains
+    fn descendants(&self, id: usize) -> HashSet<usize> {
+        self.reachable(id, &self.contains)
+    }
+
+    /// The shortest containment chain from `from` down to `to`, as the
+    /// `(count, id)` of each step
+    fn path(&self, from: usize, to: usize) -> Option<Vec<(usize, usize)>> {
+        let mut previous = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut to_visit = VecDeque::new();
+        visited.insert(from);
+        to_visit.push_back(from);
+
+        while let Some(x) = to_visit.pop_front() {
+            if x == to {
+                let mut path = Vec::new();
+                let mut current = to;
+                while current != from {
+                    let (count, parent) = previous[&current];
+                    path.push((count, current));
+                    current = parent;
+                }
+                path.reverse();
+                return Some(path)
+            }
+
+            for (count, other) in &self.contains[x] {
+                if visited.insert(*other) {
+                    previous.insert(*other, (*count, x));
+                    to_visit.push_back(*other);
+                }
+            }
         }
 
-        if let Some(others) = inverted.get(&x) {
-            for other in others {
-                to_visit.push_back(*other);
+        None
+    }
+
+    /// Every bag ordered so that containers come before the bags they
+    /// contain, or `None` if the rules have a cycle. With `from`, only that
+    /// bag and its descendants are ordered.
+    fn topological_order(&self, from: Option<usize>) -> Option<Vec<usize>> {
+        let included = match from {
+            Some(id) => {
+                let mut included = vec![false; self.len()];
+                included[id] = true;
+                self.descendants(id).into_iter().for_each(|b| included[b] = true);
+                included
+            },
+            None => vec![true; self.len()],
+        };
+
+        let mut incoming = (0..self.len()).map(|id| self.contained_in[id].iter().filter(|(_, other)| included[*other]).count()).collect::<Vec<_>>();
+        let mut ready = (0..self.len()).filter(|id| included[*id] && incoming[*id] == 0).collect::<VecDeque<_>>();
+        let mut order = Vec::new();
+
+        while let Some(id) = ready.pop_front() {
+            order.push(id);
+            for (_, other) in &self.contains[id] {
+                incoming[*other] -= 1;
+                if incoming[*other] == 0 {
+                    ready.push_back(*other);
+                }
             }
+        }
+
+        if order.len() == included.iter().filter(|i| **i).count() {
+            Some(order)
         } else {
-            solution.insert(x);
+            None
+        }
+    }
+
+    /// Total number of bags inside `id`, counting multiplicities. Each bag
+    /// is counted once, bottom up, so shared bags are not explored again.
+    fn bags_inside(&self, id: usize) -> Result<usize, RuleError> {
+        let order = match self.topological_order(Some(id)) {
+            Some(order) => order,
+            None => {
+                let below = self.descendants(id);
+                let cycle = self.cycles().into_iter().find(|cycle| cycle.iter().all(|b| below.contains(b)));
+                return Err(RuleError::Cycle(cycle.expect("a cycle below the bag")))
+            },
+        };
+
+        if let Some(bag) = order.iter().find(|b| self.rules[**b] == 0) {
+            return Err(self.undefined(*bag))
+        }
+
+        let mut inside = vec![0usize; self.len()];
+        for bag in order.into_iter().rev() {
+            inside[bag] = self.contains[bag].iter().try_fold(0usize, |total, (count, other)| {
+                inside[*other].checked_add(1)
+                    .and_then(|n| n.checked_mul(*count))
+                    .and_then(|n| n.checked_add(total))
+            }).ok_or(RuleError::Overflow(bag))?;
+        }
+
+        Ok(inside[id])
+    }
+
+    fn undefined(&self, bag: usize) -> RuleError {
+        let inside = self.contained_in[bag].iter().map(|(_, other)| *other).collect::<BTreeSet<_>>();
+        RuleError::Undefined { bag, inside: inside.into_iter().collect() }
+    }
+
+    /// One cycle for each edge leading back to a bag still being explored
+    /// by a depth first search
+    fn cycles(&self) -> Vec<Vec<usize>> {
+        let mut cycles = Vec::new();
+        let mut done = vec![false; self.len()];
+        let mut on_stack = vec![false; self.len()];
+
+        for start in 0..self.len() {
+            if done[start] {
+                continue
+            }
+
+            let mut stack = vec![(start, 0)];
+            on_stack[start] = true;
+            while let Some(&(id, edge)) = stack.last() {
+                match self.contains[id].get(edge) {
+                    Some((_, next)) => {
+                        stack.last_mut().unwrap().1 += 1;
+                        if on_stack[*next] {
+                            let pos = stack.iter().position(|(b, _)| b == next).unwrap();
+                            let mut cycle = stack[pos..].iter().map(|(b, _)| *b).collect::<Vec<_>>();
+                            cycle.push(*next);
+                            cycles.push(cycle);
+                        } else if !done[*next] {
+                            on_stack[*next] = true;
+                            stack.push((*next, 0));
+                        }
+                    },
+                    None => {
+                        stack.pop();
+                        on_stack[id] = false;
+                        done[id] = true;
+                    },
+                }
+            }
+        }
+
+        cycles
+    }
+
+    /// Every problem with the rules: cycles, bags without a rule, bags with
+    /// several rules and bags listed with different counts
+    fn validate(&self) -> Vec<RuleError> {
+        let mut errors = self.cycles().into_iter().map(RuleError::Cycle).collect::<Vec<_>>();
+
+        for id in 0..self.len() {
+            match self.rules[id] {
+                0 => errors.push(self.undefined(id)),
+                1 => {},
+                rules => errors.push(RuleError::DuplicateRule { bag: id, rules }),
+            }
+
+            let mut counts: Vec<(usize, Vec<usize>)> = Vec::new();
+            for (count, other) in &self.contains[id] {
+                match counts.iter_mut().find(|(b, _)| b == other) {
+                    Some((_, c)) => c.push(*count),
+                    None => counts.push((*other, vec![*count])),
+                }
+            }
+            for (contained, counts) in counts {
+                if counts.iter().any(|c| *c != counts[0]) {
+                    errors.push(RuleError::ContradictoryCount { container: id, contained, counts });
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// The rules as a Graphviz DOT or Mermaid flowchart, with edges labelled
+    /// by their count
+    fn export(&self, options: &ExportOptions) -> String {
+        let mut included = vec![true; self.len()];
+        for (bag, related) in options.from.map(|b| (b, self.descendants(b))).into_iter().chain(options.to.map(|b| (b, self.ancestors(b)))) {
+            for (id, keep) in included.iter_mut().enumerate() {
+                *keep = *keep && (id == bag || related.contains(&id));
+            }
+        }
+        let highlight = options.highlight.or(options.from).or(options.to);
+        let bags = (0..self.len()).filter(|id| included[*id]).collect::<Vec<_>>();
+        let edges = bags.iter().flat_map(|from| {
+            self.contains[*from].iter().filter(|(_, to)| included[*to]).map(move |(count, to)| (*from, *count, *to))
+        }).collect::<Vec<_>>();
+
+        let mut out = String::new();
+        match options.format {
+            ExportFormat::Dot => {
+                out.push_str("digraph bags {\n");
+                for id in &bags {
+                    let style = if Some(*id) == highlight { " [style=filled, fillcolor=gold]" } else { "" };
+                    out.push_str(&format!("    \"{}\"{};\n", self.name(*id), style));
+                }
+                for (from, count, to) in edges {
+                    out.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\"];\n", self.name(from), self.name(to), count));
+                }
+                out.push_str("}\n");
+            },
+            ExportFormat::Mermaid => {
+                out.push_str("flowchart TD\n");
+                for id in &bags {
+                    out.push_str(&format!("    b{}[\"{}\"]\n", id, self.name(*id)));
+                }
+                for (from, count, to) in edges {
+                    out.push_str(&format!("    b{} -->|{}| b{}\n", from, count, to));
+                }
+                if let Some(id) = highlight.filter(|id| included[*id]) {
+                    out.push_str(&format!("    style b{} fill:#ffd700,stroke:#333,stroke-width:2px\n", id));
+                }
+            },
         }
+
+        out
     }
 
-    solution.len()
+    fn describe(&self, error: &RuleError) -> String {
+        let names = |ids: &[usize]| ids.iter().map(|id| self.name(*id)).collect::<Vec<_>>();
+        match error {
+            RuleError::Cycle(path) => format!("cycle: {}", names(path).join(" -> ")),
+            RuleError::Undefined { bag, inside } => format!("{} bags have no rule but are inside {}", self.name(*bag), names(inside).join(", ")),
+            RuleError::DuplicateRule { bag, rules } => format!("{} bags have {} rules", self.name(*bag), rules),
+            RuleError::ContradictoryCount { container, contained, counts } => {
+                let counts = counts.iter().map(usize::to_string).collect::<Vec<_>>();
+                format!("{} bags contain {} bags with different counts: {}", self.name(*container), self.name(*contained), counts.join(", "))
+            },
+            RuleError::Unknown(name) => format!("no rule mentions {} bags", name),
+            RuleError::Overflow(bag) => format!("{} bags contain too many bags to count", self.name(*bag)),
+        }
+    }
 }
 
 mod parse {
     use nom::{
         IResult,
-        character::complete::{digit1, line_ending, alpha1},
-        bytes::complete::{tag},
-        combinator::map_res,
-        sequence::{separated_pair},
-        multi::{separated_list1},
+        character::complete::{digit1, alpha1, space0},
+        bytes::complete::tag,
+        combinator::{all_consuming, map_res, recognize, verify},
+        sequence::terminated,
+        multi::separated_list1,
         branch::alt,
     };
 
+    use std::fmt;
     use std::str::FromStr;
 
-    fn line(i: &str) -> IResult<&str, ((&str, &str), Vec<(usize, (&str, &str))>)> {
-        let (i, color_origin) = complex_color(i)?;
-        let (i, _) = tag(" bags contain ")(i)?;
-        let (i, contained) = alt((no_other_bags, some_bags))(i)?;
-        let (i, _) = tag(".")(i)?;
+    use super::Color;
 
-        Ok((i, (color_origin, contained)))
+    type Rule<'a> = (Color<'a>, Vec<(usize, Color<'a>)>);
 
+    /// Where in the input a rule could not be parsed, both 1-based
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ParseError {
+        pub line: usize,
+        pub column: usize,
+        pub text: String,
     }
 
-    fn one(i: &str) -> IResult<&str, (usize, (&str, &str))> {
-        let (i, _) = tag("1")(i)?;
-        let (i, _) = tag(" ")(i)?;
-        let (i, col) = complex_color(i)?;
-        let (i, _) = tag(" bag")(i)?;
+    impl fmt::Display for ParseError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "line {}, column {}: cannot parse rule '{}'", self.line, self.column, self.text)
+        }
+    }
+
+    impl std::error::Error for ParseError {}
+
+    fn line(i: &str) -> IResult<&str, Rule<'_>> {
+        let (i, color_origin) = color(i)?;
+        let (i, _) = tag(" bags contain ")(i)?;
+        let (i, contained) = alt((no_other_bags, some_bags))(i)?;
+        let (i, _) = terminated(tag("."), space0)(i)?;
 
+        Ok((i, (color_origin, contained)))
+    }
 
-        Ok((i, (1, col)))
+    fn bag(i: &str) -> IResult<&str, &str> {
+        alt((tag("bags"), tag("bag")))(i)
     }
 
-    fn many(i: &str) -> IResult<&str, (usize, (&str, &str))> {
+    fn count(i: &str) -> IResult<&str, (usize, Color<'_>)> {
         let (i, count) = map_res(digit1, FromStr::from_str)(i)?;
         let (i, _) = tag(" ")(i)?;
-        let (i, col) = complex_color(i)?;
-        let (i, _) = tag(" bags")(i)?;
-
+        let (i, col) = color(i)?;
+        let (i, _) = tag(" ")(i)?;
+        let (i, _) = bag(i)?;
 
         Ok((i, (count, col)))
     }
 
-    fn some_bags(i: &str) -> IResult<&str, Vec<(usize, (&str, &str))>> {
-        let (i, bags) = separated_list1(tag(", "), alt((one, many)))(i)?;
-
-        Ok((i, bags))
+    fn some_bags(i: &str) -> IResult<&str, Vec<(usize, Color<'_>)>> {
+        separated_list1(tag(", "), count)(i)
     }
 
-    fn no_other_bags(i: &str) -> IResult<&str, Vec<(usize, (&str, &str))>> {
+    fn no_other_bags(i: &str) -> IResult<&str, Vec<(usize, Color<'_>)>> {
         let (i, _) = tag("no other bags")(i)?;
 
         Ok((i, vec![]))
     }
 
-    fn complex_color(input: &str) -> IResult<&str, (&str, &str)> {
-        separated_pair(alpha1, tag(" "), alpha1)(input)
+    /// Any word that is not "bag" or "bags"
+    fn word(i: &str) -> IResult<&str, &str> {
+        verify(alpha1, |w: &str| w != "bag" && w != "bags")(i)
+    }
+
+    fn color(i: &str) -> IResult<&str, Color<'_>> {
+        recognize(separated_list1(tag(" "), word))(i)
     }
 
-    pub fn lines(input: &str) -> IResult<&str, Vec<((&str, &str), Vec<(usize, (&str, &str))>)>> {
-        separated_list1(line_ending, line)(input)
+    /// Parses one rule per line, skipping blank lines
+    pub fn lines(input: &str) -> Result<Vec<Rule<'_>>, ParseError> {
+        input.lines().enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(n, l)| match all_consuming(line)(l) {
+                Ok((_, rule)) => Ok(rule),
+                Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(ParseError {
+                    line: n + 1,
+                    column: l.len() - e.input.len() + 1,
+                    text: l.to_string(),
+                }),
+                Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never ask for more input"),
+            })
+            .collect()
     }
 }
 
@@ -143,6 +610,11 @@ mod tests {
 
     use super::*;
 
+    fn graph(input: &str) -> BagGraph<'_> {
+        let lines = parse::lines(input).expect("valid input");
+        BagGraph::new(lines)
+    }
+
     #[test]
     fn part1_works() {
         let input = "light red bags contain 1 bright white bag, 2 muted yellow bags.
@@ -155,7 +627,7 @@ vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.
 faded blue bags contain no other bags.
 dotted black bags contain no other bags.";
 
-        assert_eq!(4, part1(&input));
+        assert_eq!(4, part1(&graph(input)));
     }
 
 
@@ -168,8 +640,232 @@ dark yellow bags contain 2 dark green bags.
 dark green bags contain 2 dark blue bags.
 dark blue bags contain 2 dark violet bags.
 dark violet bags contain no other bags.";
-        assert_eq!(126, part2(&input));
+        assert_eq!(Ok(126), part2(&graph(input)));
+    }
+
+    #[test]
+    fn graph_works() {
+        let input = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.
+dark olive bags contain 3 faded blue bags.
+vibrant plum bags contain 5 faded blue bags.
+faded blue bags contain no other bags.";
+        let graph = graph(input);
+        let id = |c: &str| graph.id(&c).unwrap();
+        let colors = |ids: HashSet<usize>| {
+            let mut colors = ids.into_iter().map(|i| graph.name(i)).collect::<Vec<_>>();
+            colors.sort();
+            colors
+        };
+
+        assert_eq!(7, graph.len());
+        assert_eq!(vec!["bright white", "light red", "muted yellow"], colors(graph.ancestors(id("shiny gold"))));
+        assert_eq!(vec!["dark olive", "faded blue", "vibrant plum"], colors(graph.descendants(id("shiny gold"))));
+        assert!(graph.ancestors(id("light red")).is_empty());
+        assert_eq!(Ok(3 + 2 * 5 + 3), graph.bags_inside(id("shiny gold")));
+        assert_eq!(vec![(1, id("shiny gold"))], graph.contains[id("bright white")]);
+        assert_eq!(vec![(1, id("bright white")), (2, id("muted yellow"))], graph.contained_in[id("shiny gold")]);
+
+        let order = graph.topological_order(None).unwrap();
+        let position = |c: &str| order.iter().position(|i| *i == id(c)).unwrap();
+        assert!(position("light red") < position("muted yellow"));
+        assert!(position("muted yellow") < position("shiny gold"));
+        assert!(position("shiny gold") < position("vibrant plum"));
+        assert!(position("vibrant plum") < position("faded blue"));
+    }
+
+    #[test]
+    fn cycle_has_no_order() {
+        let graph = graph("dark red bags contain 1 dark blue bag.\ndark blue bags contain 2 dark red bags.");
+        assert_eq!(None, graph.topological_order(None));
+    }
+
+    #[test]
+    fn queries() {
+        let graph = graph("light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.
+dark olive bags contain 3 faded blue bags.
+vibrant plum bags contain 5 faded blue bags.
+faded blue bags contain no other bags.");
+        let query = |q: &[&str]| run_query(&graph, &q.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(Ok("3 colours can eventually contain shiny gold bags\n".to_string()), query(&["contains", "shiny", "gold"]));
+        assert_eq!(Ok("shiny gold bags must contain 16 bags of 3 colours\n".to_string()), query(&["inside", "shiny gold"]));
+        assert_eq!(Ok("bright white bags directly contain:\n  1 shiny gold\nbright white bags are directly inside:\n  1 light red\n".to_string()), query(&["direct", "bright", "white"]));
+        assert_eq!(Ok("light red -> 2 muted yellow -> 9 faded blue (18 bags)\n".to_string()), query(&["path", "light red", "faded blue"]));
+        assert_eq!(Ok("faded blue bags cannot contain light red bags\n".to_string()), query(&["path", "faded blue", "light red"]));
+        assert_eq!(Ok("shiny gold (1 bags)\n".to_string()), query(&["path", "shiny gold", "shiny gold"]));
+
+        assert_eq!(Err("unknown colour 'shiny glod', did you mean 'shiny gold'?".to_string()), query(&["contains", "shiny", "glod"]));
+        assert_eq!(Err("unknown colour 'mute yellow', did you mean 'muted yellow'?".to_string()), query(&["path", "light red", "mute yellow"]));
+        assert!(query(&["path", "light", "red", "faded blue"]).is_err());
+        assert!(query(&["count", "shiny gold"]).is_err());
+    }
+
+    #[test]
+    fn edit_distance_works() {
+        assert_eq!(0, edit_distance("shiny gold", "shiny gold"));
+        assert_eq!(2, edit_distance("shiny glod", "shiny gold"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+        assert_eq!(4, edit_distance("", "gold"));
+    }
+
+    #[test]
+    fn validate_works() {
+        let graph = graph("light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+shiny gold bags contain 1 dark olive bag.
+dark olive bags contain 2 bright white bags, 3 faded blue bags.
+faded blue bags contain no other bags.
+light red bags contain 3 muted yellow bags.");
+        let errors = graph.validate().iter().map(|e| graph.describe(e)).collect::<Vec<_>>();
+        assert_eq!(vec![
+            "cycle: bright white -> shiny gold -> dark olive -> bright white",
+            "light red bags have 2 rules",
+            "light red bags contain muted yellow bags with different counts: 2, 3",
+            "muted yellow bags have no rule but are inside light red",
+        ], errors);
+
+        assert!(graph.validate().contains(&RuleError::Cycle(vec![1, 3, 4, 1])));
+        assert!(self::graph("faded blue bags contain no other bags.").validate().is_empty());
+    }
+
+    #[test]
+    fn bags_inside_reports_errors() {
+        let graph = graph("light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+shiny gold bags contain 1 dark olive bag.
+dark olive bags contain 2 bright white bags.");
+        let id = |c: &str| graph.id(&c).unwrap();
+        assert_eq!(Err(RuleError::Cycle(vec![id("bright white"), id("shiny gold"), id("dark olive"), id("bright white")])), graph.bags_inside(id("light red")));
+        assert_eq!(Err(RuleError::Undefined { bag: id("muted yellow"), inside: vec![id("light red")] }), graph.bags_inside(id("muted yellow")));
+        assert_eq!(Err(RuleError::Unknown("shiny gold".to_string())), part2(&self::graph("faded blue bags contain no other bags.")));
+    }
+
+    /// A two word colour made of letters only, unique for each `i`
+    fn generated_color(i: usize) -> String {
+        let mut word = String::new();
+        let mut n = i;
+        loop {
+            word.push((b'a' + (n % 26) as u8) as char);
+            n /= 26;
+            if n == 0 {
+                break
+            }
+        }
+        format!("gen {}", word)
+    }
+
+    /// `n` bags, each containing `count` of each of the next two bags
+    fn generated_rules(n: usize, count: usize) -> String {
+        (0..n).map(|i| {
+            let contents = (i + 1..n.min(i + 3)).map(|j| format!("{} {} {}", count, generated_color(j), if count == 1 { "bag" } else { "bags" })).collect::<Vec<_>>();
+            if contents.is_empty() {
+                format!("{} bags contain no other bags.", generated_color(i))
+            } else {
+                format!("{} bags contain {}.", generated_color(i), contents.join(", "))
+            }
+        }).collect::<Vec<_>>().join("\n")
     }
 
+    #[test]
+    fn bags_inside_deep_rules() {
+        let input = generated_rules(3000, 1);
+        let graph = graph(&input);
+        let id = |c: &str| graph.id(&c).unwrap();
+        assert_eq!(3000, graph.len());
+
+        // The k-th bag from the bottom holds fib(k + 3) - 2 bags: 0, 1, 3, 6, 11, ...
+        let fib = |n: usize| (0..n).fold((0usize, 1usize), |(a, b), _| (b, a + b)).0;
+        assert_eq!(Ok(fib(13) - 2), graph.bags_inside(id(&generated_color(3000 - 11))));
+        assert_eq!(Ok(fib(91) - 2), graph.bags_inside(id(&generated_color(3000 - 89))));
+        assert_eq!(Err(RuleError::Overflow(id(&generated_color(3000 - 92)))), graph.bags_inside(id(&generated_color(0))));
+    }
+
+    #[test]
+    fn bags_inside_high_counts() {
+        let input = generated_rules(20, 1000);
+        let graph = graph(&input);
+        let id = |c: &str| graph.id(&c).unwrap();
+        assert_eq!(Ok(1000), graph.bags_inside(id(&generated_color(18))));
+        assert_eq!(Ok(1000 + 1000 * 1001), graph.bags_inside(id(&generated_color(17))));
+        assert!(matches!(graph.bags_inside(id(&generated_color(0))), Err(RuleError::Overflow(_))));
+
+        let query = |q: &[&str]| run_query(&graph, &q.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_eq!(Ok("gen r -> 1000 gen t (1000 bags)\n".to_string()), query(&["path", "gen r", "gen t"]));
+        assert_eq!(Err("gen a bags contain too many bags to count".to_string()), query(&["path", "gen a", "gen t"]));
+    }
+
+    #[test]
+    fn export_works() {
+        let graph = graph("light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag.
+dark olive bags contain 3 faded blue bags.
+faded blue bags contain no other bags.");
+        let id = |c: &str| graph.id(&c).unwrap();
+        let options = |format, from, to| ExportOptions { format, from, to, highlight: None };
+
+        assert_eq!("digraph bags {
+    \"shiny gold\" [style=filled, fillcolor=gold];
+    \"faded blue\";
+    \"dark olive\";
+    \"shiny gold\" -> \"dark olive\" [label=\"1\"];
+    \"dark olive\" -> \"faded blue\" [label=\"3\"];
+}
+", graph.export(&options(ExportFormat::Dot, Some(id("shiny gold")), None)));
+
+        assert_eq!("flowchart TD
+    b0[\"light red\"]
+    b1[\"bright white\"]
+    b2[\"muted yellow\"]
+    b3[\"shiny gold\"]
+    b0 -->|1| b1
+    b0 -->|2| b2
+    b1 -->|1| b3
+    b2 -->|2| b3
+    style b0 fill:#ffd700,stroke:#333,stroke-width:2px
+", graph.export(&options(ExportFormat::Mermaid, Some(id("light red")), Some(id("shiny gold")))));
+
+        let all = graph.export(&options(ExportFormat::Mermaid, None, None));
+        assert_eq!(1 + 6 + 7, all.lines().count());
+        assert!(all.contains("    b2 -->|9| b4\n"));
+    }
+
+    #[test]
+    fn export_query() {
+        let graph = graph("light red bags contain 1 bright white bag.\nbright white bags contain no other bags.");
+        let query = |q: &[&str]| run_query(&graph, &q.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(Ok("digraph bags {\n    \"light red\";\n    \"bright white\" [style=filled, fillcolor=gold];\n    \"light red\" -> \"bright white\" [label=\"1\"];\n}\n".to_string()), query(&["export", "dot", "--highlight", "bright white"]));
+        assert_eq!(Ok("flowchart TD\n    b1[\"bright white\"]\n    style b1 fill:#ffd700,stroke:#333,stroke-width:2px\n".to_string()), query(&["export", "mermaid", "--from", "bright white"]));
+        assert!(query(&["export", "svg"]).is_err());
+        assert!(query(&["export", "dot", "--from"]).is_err());
+        assert!(query(&["export", "dot", "--from", "bright whit"]).is_err());
+    }
+
+    #[test]
+    fn parse_any_colour() {
+        let graph = graph("shiny gold bags contain 1 red bags, 2 very pale lemon yellow bag.  \n\nred bags contain no other bags.\nvery pale lemon yellow bags contain 3 red bags.\n");
+
+        assert_eq!(3, graph.len());
+        assert_eq!(Ok(2), graph.lookup("very pale lemon yellow"));
+        assert_eq!(Ok(9), part2(&graph));
+    }
 
+    #[test]
+    fn parse_errors() {
+        let input = "red bags contain no other bags.\nshiny gold bags contain 2 red baggage.\n";
+        let error = parse::lines(input).unwrap_err();
+        assert_eq!((2, 38), (error.line, error.column));
+        assert_eq!("line 2, column 38: cannot parse rule 'shiny gold bags contain 2 red baggage.'", error.to_string());
+
+        assert_eq!(1, parse::lines("bags contain no other bags.").unwrap_err().column);
+        assert_eq!(31, parse::lines("red bags contain no other bags").unwrap_err().column);
+    }
 }
@@ -1,216 +1,147 @@
-use std::fs::File;
-use std::io::{self, Read};
-
-use std::collections::HashSet;
-
-fn main() -> io::Result<()> {
-    let input = std::env::args().skip(1).next().expect("Specify an input");
-    println!("Input is {}", input);
-    let mut f = File::open(input)?;
-    let mut s = String::new();
-    f.read_to_string(&mut s)?;
-
-    let (_left, program) = parse::program(&s).expect("Invalid program");
-
-    let mut state = State::default();
-    let mut visited = HashSet::new();
-
-    loop {
-        if visited.contains(&state.pc) {
-            break;
-        }
-
-        visited.insert(state.pc);
-
-        let instr = &program[state.pc];
-
-        instr.apply(&mut state);
-    }
-
-    println!("Part 01: {}", state.acc);
-
-    let mut i = 0;
-    loop {
-        let mut prgrm = Program::new(&program);
-        match prgrm.instr_at(i) {
-            Some(Instr::Acc(_)) => { },
-            Some(Instr::Nop(off)) => {
-                prgrm.code[i] = Instr::Jmp(off);
-                if ExitResult::Success == prgrm.run_to_completion() {
-                    println!("Part 02: {}", prgrm.state.acc);
-                    break
-                }
-            },
-            Some(Instr::Jmp(off)) => {
-                prgrm.code[i] = Instr::Nop(off);
-                if ExitResult::Success == prgrm.run_to_completion() {
-                    println!("Part 02: {}", prgrm.state.acc);
-                    break
-                }
-            },
-            None => {
-                panic!("Could not find a suitable answer")
-            }
-        }
-
-        i += 1;
-    }
-
+use std::fs::{self, File};
+use std::path::Path;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use aoc2020::handheld::{invalid_data, invalid_input, Overflow, Program};
+use aoc2020::handheld::{asm, bytecode, lint, repair};
+use aoc2020::handheld::debugger::Debugger;
+use aoc2020::handheld::opcodes::Registry;
+use aoc2020::handheld::snapshot::Snapshot;
+use aoc2020::handheld::trace::{self, Replay, TraceFormat, TraceWriter};
+
+/// Runs `program` until it stops, writing every instruction to a trace file
+fn record(mut program: Program, args: &[String]) -> io::Result<()> {
+    let (path, format) = match args {
+        [path] => (path, TraceFormat::JsonLines),
+        [path, format] => (path, match format.as_str() {
+            "json" => TraceFormat::JsonLines,
+            "binary" => TraceFormat::Binary,
+            other => return Err(invalid_input(format!("unknown trace format '{}', expected json or binary", other))),
+        }),
+        _ => return Err(invalid_input("usage: trace <output> [json|binary]".to_string())),
+    };
 
+    let mut tracer = TraceWriter::new(BufWriter::new(File::create(path)?), format)?;
+    let exit = program.run_traced(None, &mut tracer)?;
+    tracer.into_inner().flush()?;
+    println!("{:?} with acc {}", exit, program.state().acc);
     Ok(())
 }
 
-pub struct Program {
-    state: State,
-    code: Vec<Instr>,
-}
+/// Summarizes a trace file, and the state at `step` if given
+fn replay(path: &str, args: &[String]) -> io::Result<()> {
+    let replay = Replay::new(trace::read_trace(BufReader::new(File::open(path)?), &Registry::default())?);
+    println!("{} steps", replay.len());
 
-impl Program {
-    fn new(instructions: &[Instr]) -> Self {
-        Program{
-            state: Default::default(),
-            code: instructions.iter().map(|i| (*i).clone()).collect()
+    if let [step] = args {
+        let n = step.parse().map_err(|_| invalid_input(format!("invalid step '{}'", step)))?;
+        match replay.state_at(n) {
+            Some(state) => println!("step {}: pc {}, acc {}", n, state.pc, state.acc),
+            None => return Err(invalid_input(format!("step {} is past the end of the trace", n))),
         }
     }
 
-    pub fn next_instr(&self) -> Instr {
-        self.code[self.state.pc].clone()
+    for (pc, (instr, hits)) in replay.hit_counts() {
+        println!("{:>5}  {:<10}{}", pc, instr.to_string(), hits);
     }
 
-    pub fn instr_at(&self, i: usize) -> Option<Instr> {
-        self.code.get(i).map(|x| (*x).clone())
+    match replay.loop_body() {
+        Some(body) => println!("loop: {}", body.iter().map(|pc| pc.to_string()).collect::<Vec<_>>().join(" ")),
+        None => println!("no loop"),
     }
-
-    pub fn run_to_completion(&mut self) -> ExitResult {
-        let mut visited = HashSet::new();
-        loop {
-            if visited.contains(&self.state.pc) {
-                return ExitResult::InfiniteLoop;
-            }
-
-            if self.state.pc >= self.code.len() {
-                return ExitResult::Success
-            }
-
-            visited.insert(self.state.pc);
-
-            let instr = &self.code[self.state.pc];
-
-            instr.apply(&mut self.state);
-        }
-    }
-}
-
-#[derive(Debug, Eq, PartialEq)]
-pub enum ExitResult {
-    InfiniteLoop,
-    Success,
-}
-
-#[derive(Debug, Eq, PartialEq, Default)]
-pub struct State {
-    pc: usize,
-    acc: i64,
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
-pub enum Instr {
-    Nop(i64),
-    Acc(i64),
-    Jmp(i64),
-}
+/// Runs `program` for at most `steps` instructions, starting from the
+/// snapshot file if it exists and saving the new state there afterwards
+fn checkpoint(mut program: Program, args: &[String]) -> io::Result<()> {
+    let (path, steps) = match args {
+        [path, steps] => (path, steps.parse().map_err(|_| invalid_input(format!("invalid step count '{}'", steps)))?),
+        _ => return Err(invalid_input("usage: checkpoint <snapshot> <steps>".to_string())),
+    };
 
-impl Instr {
-    fn apply(&self, state: &mut State) {
-        match self {
-            Instr::Nop(_offset) => { state.pc += 1; },
-            Instr::Jmp(offset) => { state.pc = (state.pc as i64 + *offset) as usize; },
-            Instr::Acc(offset) => { state.pc +=1; state.acc += offset; }
-        }
+    if Path::new(path).exists() {
+        program.restore(fs::read_to_string(path)?.parse::<Snapshot>()?)?;
     }
+    let exit = program.run_with_limit(steps);
+    fs::write(path, program.snapshot().to_string())?;
+    println!("{:?} with acc {}", exit, program.state().acc);
+    Ok(())
 }
 
-mod parse {
-    use super::Instr;
-    use nom::{
-        IResult,
-        character::complete::{digit1, line_ending},
-        bytes::complete::{tag},
-        combinator::{map_res, map},
-        sequence::{terminated},
-        multi::{many1},
-        branch::alt,
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let overflow = match args.iter().position(|arg| arg == "--overflow") {
+        Some(i) if i + 1 < args.len() => args.drain(i..i + 2).nth(1).expect("two arguments drained").parse()?,
+        Some(_) => return Err(invalid_input("usage: --overflow trap|wrap|saturate".to_string())),
+        None => Overflow::default(),
     };
-
-    fn number(i: &str) -> IResult<&str, i64> {
-        let (i, sign) = alt((map(tag("+"), |_s: &str| 1), map(tag("-"), |_s: &str| -1)))(i)?;
-        let (i, num) = map_res(digit1, |s: &str| s.parse::<i64>())(i)?;
-
-        Ok((i, sign * num))
-    }
-
-    fn nop(i: &str) -> IResult<&str, Instr> {
-        let (i, _) = tag("nop")(i)?;
-        let (i, _) = tag(" ")(i)?;
-        let (i, offset) = number(i)?;
-
-        Ok((i, Instr::Nop(offset)))
-    }
-
-    fn jmp(i: &str) -> IResult<&str, Instr> {
-        let (i, _) = tag("jmp")(i)?;
-        let (i, _) = tag(" ")(i)?;
-        let (i, num) = number(i)?;
-
-        Ok((i, Instr::Jmp(num)))
-    }
-
-    fn acc(i: &str) -> IResult<&str, Instr> {
-        let (i, _) = tag("acc")(i)?;
-        let (i, _) = tag(" ")(i)?;
-        let (i, num) = number(i)?;
-
-        Ok((i, Instr::Acc(num)))
-    }
-
-    fn line(i: &str) -> IResult<&str, Instr> {
-        terminated(alt((nop, jmp, acc)), line_ending)(i)
-    }
-
-    pub fn program(i: &str) -> IResult<&str, Vec<Instr>> {
-        many1(line)(i)
+    let mut args = args.into_iter();
+    let input = args.next().expect("Specify an input");
+    let mode = args.next();
+    let rest = args.collect::<Vec<_>>();
+
+    if mode.as_deref() == Some("replay") {
+        return replay(&input, &rest)
+    }
+
+    let registry = Registry::default();
+    if mode.as_deref() == Some("assemble") {
+        let mut f = File::open(&input)?;
+        let mut s = String::new();
+        f.read_to_string(&mut s)?;
+
+        let code = asm::assemble(&s, &registry).map_err(|e| invalid_data(e.to_string()))?;
+        code.iter().for_each(|instr| println!("{}", instr));
+        return Ok(())
+    }
+
+    let mut program = bytecode::load(&input, &registry)?;
+    program.set_overflow(overflow);
+
+    match mode.as_deref() {
+        None => {},
+        Some("debug") => return Debugger::new(program).run(io::stdin().lock(), io::stdout()),
+        Some("trace") => return record(program, &rest),
+        Some("disassemble") => {
+            print!("{}", asm::disassemble(&program.code()));
+            return Ok(())
+        },
+        Some("compile") => {
+            let path = rest.first().ok_or_else(|| invalid_input("usage: compile <output>".to_string()))?;
+            let mut out = BufWriter::new(File::create(path)?);
+            bytecode::write_program(&program.code(), &mut out)?;
+            return out.flush()
+        },
+        Some("checkpoint") => return checkpoint(program, &rest),
+        Some("lint") => {
+            let findings = lint::lint(&program.code());
+            findings.iter().for_each(|finding| println!("{}", finding));
+            if findings.is_empty() {
+                println!("no problems found");
+            }
+            return Ok(())
+        },
+        Some("decompile") => {
+            program.code().iter().for_each(|instr| println!("{}", instr));
+            return Ok(())
+        },
+        Some(other) => return Err(invalid_input(format!(
+            "unknown mode '{}', expected debug, trace, replay, assemble, disassemble, compile, decompile, lint or checkpoint",
+            other,
+        ))),
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn nop_works() {
-        let mut state = State::default();
-        Instr::Nop(0).apply(&mut state);
-        assert_eq!(1, state.pc);
-        assert_eq!(0, state.acc);
-    }
+    println!("Input is {}", input);
 
-    #[test]
-    fn jmp_works() {
-        let mut state = State::default();
-        Instr::Jmp(2).apply(&mut state);
-        assert_eq!(2, state.pc);
-        assert_eq!(0, state.acc);
+    let mut prgrm = program.clone();
+    prgrm.run_to_completion();
+    println!("Part 01: {}", prgrm.state().acc);
 
-        Instr::Jmp(-2).apply(&mut state);
-        assert_eq!(0, state.pc);
-        assert_eq!(0, state.acc);
+    match repair::repairs(&program.code()) {
+        Ok(repairs) => println!("Part 02: {}", repairs[0].acc),
+        Err(e) => println!("Part 02: {}", e),
     }
 
-    #[test]
-    fn acc_works() {
-        let mut state = State::default();
-        Instr::Acc(25).apply(&mut state);
-        assert_eq!(1, state.pc);
-        assert_eq!(25, state.acc);
-    }
+    Ok(())
 }
@@ -2,46 +2,144 @@ use std::io::{self, BufRead, BufReader};
 use std::env;
 use std::fs::File;
 
+use std::collections::{HashMap, VecDeque};
+
 fn main() -> io::Result<()> {
-    let file = env::args().skip(1).next().unwrap();
+    let mut args = env::args().skip(1);
+    let file = args.next().expect("Specify an input");
+    let mut preamble = 25;
+    let mut all = false;
+    for arg in args {
+        match arg.as_str() {
+            "all" => all = true,
+            n => preamble = n.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid preamble length '{}'", n)))?,
+        }
+    }
+
     let f = File::open(file)?;
     let f = BufReader::new(f);
-    let numbers = f.lines().map(|l| l.unwrap().parse().unwrap()).collect::<Vec<u64>>();
-    let mut weakness = 0;
-    let mut index = 0;
-    for wnd in numbers.windows(26) {
-        let mut found = false;
-        let needle = wnd[25];
-        'outer: for i in 0..=24 {
-            for j in 0..=24 {
-                if i != j {
-                    if wnd[i] + wnd[j] == needle {
-                        found = true;
-                        break 'outer
+    let numbers = f.lines()
+        .map(|l| l?.trim().parse::<u64>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut invalid = invalid_numbers(preamble, numbers.iter().copied());
+    if all {
+        invalid.for_each(|(index, n)| println!("{}: {}", index, n));
+        return Ok(())
+    }
+
+    let (index, needle) = match invalid.next() {
+        Some(first) => first,
+        None => {
+            println!("Part 01: every number is valid");
+            return Ok(())
+        },
+    };
+    println!("Part 01: {}", needle);
+
+    match weakness(&numbers[..index], needle) {
+        Some(weakness) => println!("Part 02: {}", weakness),
+        None => println!("Part 02: no contiguous range adds up to {}", needle),
+    }
+
+    Ok(())
+}
+
+/// Checks numbers one at a time against the `preamble` numbers before them,
+/// keeping every sum of two of those numbers so each check is a lookup
+struct XmasValidator {
+    preamble: usize,
+    window: VecDeque<u64>,
+    sums: HashMap<u64, usize>,
+}
+
+impl XmasValidator {
+    fn new(preamble: usize) -> Self {
+        XmasValidator { preamble, window: VecDeque::with_capacity(preamble + 1), sums: HashMap::new() }
+    }
+
+    /// The sums of `n` with each number in the window. `n` is never in the
+    /// window itself, so equal values here are at different positions.
+    fn sums_with(window: &VecDeque<u64>, n: u64) -> impl Iterator<Item = u64> + '_ {
+        window.iter().filter_map(move |w| w.checked_add(n))
+    }
+
+    /// Whether `n` is the sum of two numbers at different positions in the
+    /// window, even if their values are equal, which
+    /// numbers in the preamble always are. `n` then joins the window.
+    fn push(&mut self, n: u64) -> bool {
+        let valid = self.window.len() < self.preamble || self.sums.contains_key(&n);
+
+        for sum in Self::sums_with(&self.window, n) {
+            *self.sums.entry(sum).or_insert(0) += 1;
+        }
+        self.window.push_back(n);
+
+        if self.window.len() > self.preamble {
+            let oldest = self.window.pop_front().expect("the window is not empty");
+            for sum in Self::sums_with(&self.window, oldest) {
+                if let Some(count) = self.sums.get_mut(&sum) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.sums.remove(&sum);
                     }
                 }
             }
         }
 
-        if !found {
-            weakness = needle;
-            println!("Part 01: {}", needle);
-            break
-        }
-        index +=1;
+        valid
     }
+}
 
+/// Every number that is not the sum of two of the `preamble` numbers before
+/// it, along with its index
+fn invalid_numbers<I: IntoIterator<Item = u64>>(preamble: usize, numbers: I) -> impl Iterator<Item = (usize, u64)> {
+    let mut validator = XmasValidator::new(preamble);
+    numbers.into_iter().enumerate().filter(move |(_, n)| !validator.push(*n))
+}
 
-    let possible = &numbers[0..index];
+/// The sum of the smallest and largest numbers in a contiguous range of at
+/// least two numbers adding up to `target`
+fn weakness(numbers: &[u64], target: u64) -> Option<u64> {
+    (2..=numbers.len())
+        .flat_map(|size| numbers.windows(size))
+        .find(|wnd| wnd.iter().sum::<u64>() == target)
+        .map(|wnd| wnd.iter().min().unwrap() + wnd.iter().max().unwrap())
+}
 
-    'all: for window_size in 2..index {
-        for wnd in possible.windows(window_size) {
-            if wnd.iter().fold(0, |s, x| s + *x) == weakness {
-                println!("Part 02: {}", wnd.iter().min().unwrap() + wnd.iter().max().unwrap());
-                break 'all
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [u64; 20] = [35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309, 576];
+
+    #[test]
+    fn example_works() {
+        let invalid = invalid_numbers(5, EXAMPLE.iter().copied()).collect::<Vec<_>>();
+        assert_eq!(vec![(14, 127)], invalid);
+        assert_eq!(Some(62), weakness(&EXAMPLE[..14], 127));
     }
 
-    Ok(())
+    #[test]
+    fn preamble_of_25() {
+        let preamble = (1..=25).rev().collect::<Vec<u64>>();
+        let check = |n| {
+            let mut validator = XmasValidator::new(25);
+            preamble.iter().for_each(|p| assert!(validator.push(*p)));
+            validator.push(n)
+        };
+
+        assert!(check(26));
+        assert!(check(49));
+        assert!(!check(100));
+        assert!(!check(50));
+    }
+
+    #[test]
+    fn yields_every_invalid_number() {
+        let numbers = vec![1, 2, 3, 3, 10, 5, 8, 13, 6, 6, 12];
+        assert_eq!(vec![(3, 3), (4, 10), (5, 5), (6, 8), (8, 6), (9, 6)], invalid_numbers(2, numbers).collect::<Vec<_>>());
+        assert_eq!(Vec::<(usize, u64)>::new(), invalid_numbers(0, vec![]).collect::<Vec<_>>());
+        assert_eq!(vec![(0, 1)], invalid_numbers(0, vec![1]).collect::<Vec<_>>());
+    }
 }
@@ -3,7 +3,7 @@ use std::env;
 use std::fs::File;
 
 fn main() -> io::Result<()> {
-    let file = env::args().skip(1).next().unwrap();
+    let file = env::args().nth(1).unwrap();
     let f = File::open(file)?;
     let f = BufReader::new(f);
     let mut numbers = f.lines().map(|l| l.unwrap().parse().unwrap()).collect::<Vec<u64>>();
@@ -19,7 +19,7 @@ fn main() -> io::Result<()> {
 }
 
 fn combi(input: &[u64]) -> usize {
-    let mut nums = input.iter().map(|x| *x).collect::<Vec<u64>>();
+    let mut nums = input.to_vec();
     nums.sort();
     let windows = nums.windows(2).collect::<Vec<_>>();
     let parts = windows.split(|wnd| wnd[1] - wnd[0] == 3).collect::<Vec<_>>();
@@ -38,12 +38,11 @@ fn permutations(input: Vec<usize>, cache: &mut HashMap<usize, usize>) -> usize {
         return *value
     }
 
-    let mut target = input.last().unwrap();
+    let target = input.last().unwrap();
     let mut paths = Vec::new();
     let mut possibilities = 0;
     paths.push(vec![1]);
-    while !paths.is_empty() {
-        let to_test = paths.pop().unwrap();
+    while let Some(to_test) = paths.pop() {
         let last = to_test.last().unwrap();
         if last == target {
             println!("{:?} is a valid path", to_test);
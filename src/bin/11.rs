@@ -2,7 +2,7 @@ use std::io::{self, Read};
 use std::fs::File;
 
 fn main() -> io::Result<()> {
-    let file = std::env::args().skip(1).next().unwrap();
+    let file = std::env::args().nth(1).unwrap();
     let mut file = File::open(file)?;
     let mut s = String::new();
     file.read_to_string(&mut s)?;
@@ -97,7 +97,7 @@ impl convert::TryFrom<char> for Tile {
             'L' => Ok(Tile::Empty),
             '.' => Ok(Tile::Floor),
             '#' => Ok(Tile::Occupied),
-            _ => Err(io::Error::new(io::ErrorKind::Other, "invalid tile type"))
+            _ => Err(io::Error::other("invalid tile type"))
         }
     }
 }
@@ -121,7 +121,7 @@ impl FromStr for Tile {
             'L' => Ok(Tile::Empty),
             '.' => Ok(Tile::Floor),
             '#' => Ok(Tile::Occupied),
-            _ => Err(io::Error::new(io::ErrorKind::Other, "invalid tile type"))
+            _ => Err(io::Error::other("invalid tile type"))
         }
     }
 }
@@ -300,7 +300,7 @@ impl fmt::Display for State {
             for col in row {
                 write!(f, "{}", col)?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
 
         Ok(())
@@ -317,7 +317,7 @@ struct Change {
 
 impl Change {
     fn apply(&self, state: &mut State) {
-        let ref existing = state.grid[self.y][self.x];
+        let existing = &state.grid[self.y][self.x];
         if existing == &self.into || existing == &Tile::Floor {
             panic!("Trying to change");
         }
@@ -2,7 +2,7 @@ use std::io::{self, Read};
 use std::fs::File;
 
 fn main() -> io::Result<()> {
-    let file = std::env::args().skip(1).next().unwrap();
+    let file = std::env::args().nth(1).unwrap();
     let mut file = File::open(file)?;
     let mut s = String::new();
     file.read_to_string(&mut s)?;
@@ -52,8 +52,8 @@ fn part2(s: &str) -> i64 {
             "E" => { waypoint = move_n_times(&Direction::East, offset as usize, &waypoint); },
             "W" => { waypoint = move_n_times(&Direction::West, offset as usize, &waypoint); },
             "F" => { coords = (coords.0 + offset * waypoint.0, coords.1 + offset * waypoint.1); },
-            "L" => { for _i in 0..(offset/90) { waypoint = (waypoint.1, -1 * waypoint.0); }},
-            "R" => { for _i in 0..(offset/90) { waypoint = (-1 * waypoint.1, waypoint.0); }},
+            "L" => { for _i in 0..(offset/90) { waypoint = (waypoint.1, -waypoint.0); }},
+            "R" => { for _i in 0..(offset/90) { waypoint = (-waypoint.1, waypoint.0); }},
             _ => { panic!("Did not expect a '{}'", instr); }
         }
     }
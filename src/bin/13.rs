@@ -4,7 +4,7 @@ use std::fs::File;
 use std::collections::BinaryHeap;
 
 fn main() -> io::Result<()> {
-    let f = env::args().skip(1).next().unwrap();
+    let f = env::args().nth(1).unwrap();
     let mut file = File::open(f)?;
     let mut s = String::new();
     file.read_to_string(&mut s)?;
@@ -39,27 +39,12 @@ fn part1(arrival_time: i64, buses: &[(i64, i64)]) -> i64 {
     0
 }
 
-fn part2(buses: &[(i64, i64)]) -> i64 {
-    let stride = buses.iter().max_by(|(id_a, _), (id_b, _)| id_a.cmp(id_b)).unwrap();
-    let rels = buses.iter().map(|(id, off)| (*id, off - stride.1)).collect::<Vec<_>>();
-    let mut t = stride.0;
-    println!("stride is {:?}", stride);
-    loop {
-        if rels.iter().all(|(id, off)| (t + off) % *id == 0) {
-            let first = rels.iter().map(|(_, off)| *off).min().unwrap();
-            return t + first;
-        }
-
-        t += stride.0;
-    }
-}
-
 fn part2_crt(buses: &[(i64, i64)]) -> i64 {
-    let M: i64 = buses.iter().map(|x| x.0).product();
+    let modulus: i64 = buses.iter().map(|x| x.0).product();
 
     buses.iter().map(|(m, a)| {
-        a * (M/m) * mod_inv(M/m, *m).unwrap()
-    }).sum::<i64>() % M
+        a * (modulus/m) * mod_inv(modulus/m, *m).unwrap()
+    }).sum::<i64>() % modulus
 }
 
 fn egcd(a: i64, b: i64) -> (i64, i64, i64) {
@@ -95,7 +80,7 @@ impl Ord for Bus {
 
 impl PartialOrd for Bus {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(other.next_arrival.cmp(&self.next_arrival))
+        Some(self.cmp(other))
     }
 }
 
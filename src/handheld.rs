@@ -0,0 +1,700 @@
+//! The handheld game console from day 8: a tiny VM with a program counter,
+//! an accumulator and three core instructions. `opcodes` adds more
+//! registers and instructions on top.
+//!
+//! ```
+//! use aoc2020::handheld::{ExitResult, Program};
+//!
+//! let mut program: Program = "acc +3\njmp -1\n".parse().unwrap();
+//! assert_eq!(ExitResult::InfiniteLoop { pc: 0 }, program.run_to_completion());
+//! assert_eq!(3, program.state().acc);
+//! ```
+
+pub mod asm;
+pub mod bytecode;
+pub mod debugger;
+pub mod lint;
+pub mod opcodes;
+pub mod repair;
+pub mod snapshot;
+pub mod trace;
+mod varint;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use opcodes::{Op, Registry};
+use snapshot::Snapshot;
+use trace::{Event, Tracer};
+
+/// The error for malformed programs, bytecode, traces and snapshots
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The error for bad arguments, such as an unknown mode or a snapshot of
+/// another program
+pub fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// A single instruction. The operand is the signed number after the opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Nop(i64),
+    Acc(i64),
+    Jmp(i64),
+    /// Anything from an `opcodes::Registry`
+    Op(Op),
+}
+
+impl Instr {
+    /// Executes the instruction against `state`, moving the program counter.
+    /// On a fault `state` is left as it was.
+    pub fn apply(&self, state: &mut State, overflow: Overflow) -> Result<(), Fault> {
+        match self {
+            Instr::Nop(_offset) => state.jump(1),
+            Instr::Jmp(offset) => state.jump(*offset),
+            Instr::Acc(offset) => {
+                let acc = overflow.add(state.acc, *offset)?;
+                state.jump(1)?;
+                state.acc = acc;
+                Ok(())
+            },
+            Instr::Op(op) => (op.opcode().exec)(op.args(), state, overflow),
+        }
+    }
+
+    /// The byte identifying the instruction in binary formats
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Instr::Nop(_) => 0,
+            Instr::Acc(_) => 1,
+            Instr::Jmp(_) => 2,
+            Instr::Op(op) => op.opcode().code,
+        }
+    }
+
+    /// Writes the opcode byte followed by each operand as a varint
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&[self.opcode()])?;
+        match self {
+            Instr::Nop(n) | Instr::Acc(n) | Instr::Jmp(n) => varint::write_i64(out, *n),
+            Instr::Op(op) => op.args()[..op.opcode().operands.len()].iter().try_for_each(|arg| varint::write_i64(out, *arg)),
+        }
+    }
+
+    /// The reverse of `write`, looking extended opcodes up in `registry`
+    pub fn read<R: Read>(input: &mut R, registry: &Registry) -> io::Result<Instr> {
+        let mut code = [0];
+        input.read_exact(&mut code)?;
+        match code[0] {
+            0 => Ok(Instr::Nop(varint::read_i64(input)?)),
+            1 => Ok(Instr::Acc(varint::read_i64(input)?)),
+            2 => Ok(Instr::Jmp(varint::read_i64(input)?)),
+            code => {
+                let opcode = registry.by_code(code).ok_or_else(|| invalid_data(format!("unknown opcode {}", code)))?;
+                let mut args = [0; 2];
+                for arg in args.iter_mut().take(opcode.operands.len()) {
+                    *arg = varint::read_i64(input)?;
+                }
+                let op = Op::new(opcode, args).ok_or_else(|| invalid_data(format!("invalid register operand for {}", opcode.name)))?;
+                Ok(Instr::Op(op))
+            },
+        }
+    }
+}
+
+/// Parses a single instruction, including the built-in extended ones
+impl FromStr for Instr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse::instr(s, &Registry::default()).ok_or_else(|| ParseError { line: 1, text: s.to_string() })
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Nop(n) => write!(f, "nop {:+}", n),
+            Instr::Acc(n) => write!(f, "acc {:+}", n),
+            Instr::Jmp(n) => write!(f, "jmp {:+}", n),
+            Instr::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// The registers of the console, and everything written by `out`
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct State {
+    pub pc: usize,
+    pub acc: i64,
+    /// Registers `a` to `d`
+    pub regs: [i64; 4],
+    pub output: Vec<i64>,
+}
+
+impl State {
+    /// A register by its index in `opcodes::REGISTERS`. Panics for any other
+    /// index; `Op` only holds valid register operands.
+    pub fn register(&self, index: i64) -> i64 {
+        match index {
+            0 => self.acc,
+            i => self.regs[i as usize - 1],
+        }
+    }
+
+    pub fn register_mut(&mut self, index: i64) -> &mut i64 {
+        match index {
+            0 => &mut self.acc,
+            i => &mut self.regs[i as usize - 1],
+        }
+    }
+
+    /// Moves the program counter by `offset`, failing rather than wrapping
+    /// around if that goes below 0 or past `usize::MAX`
+    pub fn jump(&mut self, offset: i64) -> Result<(), Fault> {
+        let target = self.pc as i128 + offset as i128;
+        self.pc = usize::try_from(target).map_err(|_| Fault::InvalidJump { target })?;
+        Ok(())
+    }
+}
+
+/// What arithmetic does when a result does not fit in an `i64`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Overflow {
+    /// Stop the program with `ExitResult::Overflow`
+    #[default]
+    Trap,
+    Wrap,
+    Saturate,
+}
+
+impl Overflow {
+    pub fn add(self, a: i64, b: i64) -> Result<i64, Fault> {
+        match self {
+            Overflow::Trap => a.checked_add(b).ok_or(Fault::Overflow),
+            Overflow::Wrap => Ok(a.wrapping_add(b)),
+            Overflow::Saturate => Ok(a.saturating_add(b)),
+        }
+    }
+
+    pub fn mul(self, a: i64, b: i64) -> Result<i64, Fault> {
+        match self {
+            Overflow::Trap => a.checked_mul(b).ok_or(Fault::Overflow),
+            Overflow::Wrap => Ok(a.wrapping_mul(b)),
+            Overflow::Saturate => Ok(a.saturating_mul(b)),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Overflow::Trap => write!(f, "trap"),
+            Overflow::Wrap => write!(f, "wrap"),
+            Overflow::Saturate => write!(f, "saturate"),
+        }
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trap" => Ok(Overflow::Trap),
+            "wrap" => Ok(Overflow::Wrap),
+            "saturate" => Ok(Overflow::Saturate),
+            _ => Err(invalid_input(format!("unknown overflow mode '{}', expected trap, wrap or saturate", s))),
+        }
+    }
+}
+
+/// Why an instruction could not run
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Fault {
+    Overflow,
+    /// A jump to `target`, which is not a possible program counter
+    InvalidJump { target: i128 },
+}
+
+/// Why a program stopped running
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExitResult {
+    /// The program counter landed just past the last instruction
+    Terminated,
+    /// The instruction at `pc` was about to run a second time
+    InfiniteLoop { pc: usize },
+    /// The program counter was set anywhere else outside the program
+    OutOfBounds { pc: usize },
+    /// The instruction at `pc` tried to jump to `target`, which is before
+    /// the start or past the end of the program
+    InvalidJump { pc: usize, target: i128 },
+    /// The instruction at `pc` overflowed while trapping on overflow
+    Overflow { pc: usize },
+    /// The step budget ran out before the program stopped
+    StepLimit,
+}
+
+/// A line of program text that is not a valid instruction, 1-based
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub text: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: invalid instruction '{}'", self.line, self.text)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A program together with the state of the console running it.
+///
+/// The code is shared between clones, and `patch` changes single
+/// instructions without copying the rest, so trying out many variants of a
+/// program is cheap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    state: State,
+    code: Arc<[Instr]>,
+    patches: BTreeMap<usize, Instr>,
+    visited: HashSet<usize>,
+    overflow: Overflow,
+}
+
+impl Program {
+    pub fn new(code: Vec<Instr>) -> Self {
+        Program::shared(code.into())
+    }
+
+    /// A program running `code` without copying it
+    pub fn shared(code: Arc<[Instr]>) -> Self {
+        Program {
+            state: State::default(),
+            code,
+            patches: BTreeMap::new(),
+            visited: HashSet::new(),
+            overflow: Overflow::default(),
+        }
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Parses like `from_str`, with the extended instructions in `registry`
+    pub fn parse_with(s: &str, registry: &Registry) -> Result<Self, ParseError> {
+        parse::program(s, registry).map(Program::new)
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    /// The code as it runs, patches included. Only copies it if patched.
+    pub fn code(&self) -> Cow<'_, [Instr]> {
+        match self.patches.is_empty() {
+            true => Cow::Borrowed(&self.code),
+            false => Cow::Owned((0..self.len()).map(|pc| self.patches.get(&pc).copied().unwrap_or(self.code[pc])).collect()),
+        }
+    }
+
+    /// The shared code underneath the patches
+    pub fn base(&self) -> &Arc<[Instr]> {
+        &self.code
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Replaces the instruction at `pc` for this program only
+    ///
+    /// # Panics
+    ///
+    /// If `pc` is outside the program
+    pub fn patch(&mut self, pc: usize, instr: Instr) {
+        assert!(pc < self.len(), "cannot patch {} in a program of {} instructions", pc, self.len());
+        match self.code[pc] == instr {
+            true => self.patches.remove(&pc),
+            false => self.patches.insert(pc, instr),
+        };
+    }
+
+    /// Every patched instruction, by pc
+    pub fn patches(&self) -> &BTreeMap<usize, Instr> {
+        &self.patches
+    }
+
+    pub fn clear_patches(&mut self) {
+        self.patches.clear();
+    }
+
+    /// Puts the registers back to zero and forgets which instructions ran,
+    /// keeping the code
+    pub fn reset(&mut self) {
+        self.state = State::default();
+        self.visited.clear();
+    }
+
+    /// The registers along with which instructions already ran, enough to
+    /// resume a run later with `restore`
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            len: self.len(),
+            fingerprint: snapshot::fingerprint(&self.code),
+            patches: self.patches.clone(),
+            overflow: self.overflow,
+            state: self.state.clone(),
+            visited: self.visited.iter().copied().collect(),
+        }
+    }
+
+    /// Resumes the run in `snapshot`, failing without any change unless it
+    /// was taken from this same program: the same base code, patches and
+    /// overflow mode
+    pub fn restore(&mut self, snapshot: Snapshot) -> io::Result<()> {
+        let different = if (snapshot.len, snapshot.fingerprint) != (self.len(), snapshot::fingerprint(&self.code)) {
+            Some("code")
+        } else if snapshot.patches != self.patches {
+            Some("set of patches")
+        } else if snapshot.overflow != self.overflow {
+            Some("overflow mode")
+        } else {
+            None
+        };
+        if let Some(different) = different {
+            return Err(invalid_input(format!("the snapshot is of a program with a different {}", different)))
+        }
+
+        self.state = snapshot.state;
+        self.visited = snapshot.visited.into_iter().collect();
+        Ok(())
+    }
+
+    pub fn instr_at(&self, i: usize) -> Option<Instr> {
+        self.patches.get(&i).or_else(|| self.code.get(i)).copied()
+    }
+
+    /// The instruction the program counter points to, if any
+    pub fn next_instr(&self) -> Option<Instr> {
+        self.instr_at(self.state.pc)
+    }
+
+    /// Whether the program counter is outside the program, and how
+    pub fn exit(&self) -> Option<ExitResult> {
+        match self.state.pc {
+            pc if pc == self.len() => Some(ExitResult::Terminated),
+            pc if pc > self.len() => Some(ExitResult::OutOfBounds { pc }),
+            _ => None,
+        }
+    }
+
+    /// Runs a single instruction, or reports why there is none to run or it
+    /// faulted. A faulting instruction leaves the state as it was.
+    pub fn step(&mut self) -> Result<(), ExitResult> {
+        let pc = self.state.pc;
+        let instr = match self.next_instr() {
+            Some(instr) => instr,
+            None => return Err(self.exit().expect("no instruction means the pc is out of the program")),
+        };
+
+        match instr.apply(&mut self.state, self.overflow) {
+            Ok(()) if self.state.pc > self.len() => {
+                let target = self.state.pc as i128;
+                self.state.pc = pc;
+                Err(ExitResult::InvalidJump { pc, target })
+            },
+            Ok(()) => {
+                self.visited.insert(pc);
+                Ok(())
+            },
+            Err(Fault::Overflow) => Err(ExitResult::Overflow { pc }),
+            Err(Fault::InvalidJump { target }) => Err(ExitResult::InvalidJump { pc, target }),
+        }
+    }
+
+    /// Runs until the program stops or an instruction is about to repeat
+    pub fn run_to_completion(&mut self) -> ExitResult {
+        self.run(None)
+    }
+
+    /// Like `run_to_completion`, giving up after `limit` instructions. The
+    /// run can be picked up again with another call.
+    pub fn run_with_limit(&mut self, limit: usize) -> ExitResult {
+        self.run(Some(limit))
+    }
+
+    fn run(&mut self, limit: Option<usize>) -> ExitResult {
+        self.run_traced(limit, &mut ()).expect("the empty tracer never fails")
+    }
+
+    /// Runs like `run_with_limit`, or `run_to_completion` without a limit,
+    /// handing every executed instruction to `tracer`
+    pub fn run_traced<T: Tracer>(&mut self, limit: Option<usize>, tracer: &mut T) -> io::Result<ExitResult> {
+        let mut steps = 0;
+        loop {
+            if let Some(exit) = self.exit() {
+                return Ok(exit)
+            }
+
+            if self.visited.contains(&self.state.pc) {
+                return Ok(ExitResult::InfiniteLoop { pc: self.state.pc });
+            }
+
+            if limit.is_some_and(|limit| steps >= limit) {
+                return Ok(ExitResult::StepLimit)
+            }
+
+            let (pc, acc_before) = (self.state.pc, self.state.acc);
+            let instr = self.next_instr().expect("the pc is inside the program");
+            if let Err(exit) = self.step() {
+                return Ok(exit)
+            }
+            tracer.record(&Event { pc, instr, acc_before, acc_after: self.state.acc, pc_after: self.state.pc })?;
+            steps += 1;
+        }
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    /// Parses one instruction per line, skipping blank lines
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Program::parse_with(s, &Registry::default())
+    }
+}
+
+mod parse {
+    use super::{Instr, ParseError};
+    use super::opcodes::{Op, OperandKind, Registry, REGISTERS};
+    use nom::{
+        IResult,
+        character::complete::{alpha1, digit1, one_of, space0},
+        bytes::complete::tag,
+        combinator::{all_consuming, map_res, opt, recognize},
+        error::{Error, ErrorKind},
+        sequence::pair,
+        branch::alt,
+    };
+
+    /// A number with a mandatory sign, parsed together with its digits so
+    /// `-9223372036854775808` fits
+    fn number(i: &str) -> IResult<&str, i64> {
+        map_res(recognize(pair(one_of("+-"), digit1)), |s: &str| s.parse::<i64>())(i)
+    }
+
+    fn nop(i: &str) -> IResult<&str, Instr> {
+        let (i, _) = tag("nop")(i)?;
+        let (i, _) = tag(" ")(i)?;
+        let (i, offset) = number(i)?;
+
+        Ok((i, Instr::Nop(offset)))
+    }
+
+    fn jmp(i: &str) -> IResult<&str, Instr> {
+        let (i, _) = tag("jmp")(i)?;
+        let (i, _) = tag(" ")(i)?;
+        let (i, num) = number(i)?;
+
+        Ok((i, Instr::Jmp(num)))
+    }
+
+    fn acc(i: &str) -> IResult<&str, Instr> {
+        let (i, _) = tag("acc")(i)?;
+        let (i, _) = tag(" ")(i)?;
+        let (i, num) = number(i)?;
+
+        Ok((i, Instr::Acc(num)))
+    }
+
+    fn register(i: &str) -> IResult<&str, i64> {
+        let (rest, name) = alpha1(i)?;
+        match REGISTERS.iter().position(|r| *r == name) {
+            Some(index) => Ok((rest, index as i64)),
+            None => Err(nom::Err::Error(Error::new(i, ErrorKind::Tag))),
+        }
+    }
+
+    fn immediate(i: &str) -> IResult<&str, i64> {
+        map_res(recognize(pair(opt(one_of("+-")), digit1)), |s: &str| s.parse::<i64>())(i)
+    }
+
+    /// An instruction from `registry`, operands separated by ", "
+    fn extended<'a>(i: &'a str, registry: &Registry) -> IResult<&'a str, Instr> {
+        let (mut i, name) = alpha1(i)?;
+        let opcode = registry.by_name(name).ok_or_else(|| nom::Err::Error(Error::new(i, ErrorKind::Tag)))?;
+        let mut args = [0; 2];
+        for (n, kind) in opcode.operands.iter().enumerate() {
+            let (rest, _) = tag(if n == 0 { " " } else { ", " })(i)?;
+            let (rest, arg) = match kind {
+                OperandKind::Register => register(rest)?,
+                OperandKind::Immediate => immediate(rest)?,
+            };
+            args[n] = arg;
+            i = rest;
+        }
+
+        let op = Op::new(opcode, args).ok_or_else(|| nom::Err::Error(Error::new(i, ErrorKind::Verify)))?;
+        Ok((i, Instr::Op(op)))
+    }
+
+    fn line<'a>(i: &'a str, registry: &Registry) -> IResult<&'a str, Instr> {
+        let (i, instr) = match alt((nop, jmp, acc))(i) {
+            Ok(parsed) => parsed,
+            Err(_) => extended(i, registry)?,
+        };
+        let (i, _) = all_consuming(space0)(i)?;
+
+        Ok((i, instr))
+    }
+
+    pub fn instr(input: &str, registry: &Registry) -> Option<Instr> {
+        line(input, registry).ok().map(|(_, instr)| instr)
+    }
+
+    pub fn program(input: &str, registry: &Registry) -> Result<Vec<Instr>, ParseError> {
+        input.lines().enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(n, l)| instr(l, registry).ok_or_else(|| ParseError { line: n + 1, text: l.to_string() }))
+            .collect()
+    }
+}
+
+/// The example program from the day 8 puzzle, which loops forever
+#[cfg(test)]
+pub(crate) const EXAMPLE: &str = "nop +0
+acc +1
+jmp +4
+acc +3
+jmp -3
+acc -99
+acc +1
+jmp -4
+acc +6
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nop_works() {
+        let mut state = State::default();
+        Instr::Nop(0).apply(&mut state, Overflow::Trap).unwrap();
+        assert_eq!(1, state.pc);
+        assert_eq!(0, state.acc);
+    }
+
+    #[test]
+    fn jmp_works() {
+        let mut state = State::default();
+        Instr::Jmp(2).apply(&mut state, Overflow::Trap).unwrap();
+        assert_eq!(2, state.pc);
+        assert_eq!(0, state.acc);
+
+        Instr::Jmp(-2).apply(&mut state, Overflow::Trap).unwrap();
+        assert_eq!(0, state.pc);
+        assert_eq!(0, state.acc);
+    }
+
+    #[test]
+    fn acc_works() {
+        let mut state = State::default();
+        Instr::Acc(25).apply(&mut state, Overflow::Trap).unwrap();
+        assert_eq!(1, state.pc);
+        assert_eq!(25, state.acc);
+    }
+
+    #[test]
+    fn parse_works() {
+        let program: Program = EXAMPLE.parse().unwrap();
+        assert_eq!(9, program.code().len());
+        assert_eq!(Some(Instr::Acc(-99)), program.instr_at(5));
+        assert_eq!(EXAMPLE, program.code().iter().map(|i| format!("{}\n", i)).collect::<String>());
+
+        assert_eq!(Err(ParseError { line: 2, text: "acc 1".to_string() }), "nop +0\nacc 1\n".parse::<Program>());
+    }
+
+    #[test]
+    fn run_works() {
+        let mut program: Program = EXAMPLE.parse().unwrap();
+        assert_eq!(ExitResult::InfiniteLoop { pc: 1 }, program.run_to_completion());
+        assert_eq!(5, program.state().acc);
+
+        program.reset();
+        program.patch(7, Instr::Nop(-4));
+        assert_eq!(ExitResult::Terminated, program.run_to_completion());
+        assert_eq!(8, program.state().acc);
+
+        program.reset();
+        assert_eq!(ExitResult::StepLimit, program.run_with_limit(3));
+        assert_eq!(6, program.state().pc);
+
+        let mut program = Program::new(vec![Instr::Jmp(5)]);
+        program.state_mut().pc = 5;
+        assert_eq!(Err(ExitResult::OutOfBounds { pc: 5 }), program.step());
+
+        program.state_mut().pc = 1;
+        assert_eq!(Err(ExitResult::Terminated), program.step());
+    }
+
+    #[test]
+    fn invalid_jumps() {
+        let run = |code: Vec<Instr>| {
+            let mut program = Program::new(code);
+            (program.run_to_completion(), program.state().pc)
+        };
+
+        assert_eq!((ExitResult::InvalidJump { pc: 1, target: -1 }, 1), run(vec![Instr::Nop(0), Instr::Jmp(-2)]));
+        assert_eq!((ExitResult::InvalidJump { pc: 0, target: 5 }, 0), run(vec![Instr::Jmp(5)]));
+        assert_eq!((ExitResult::InvalidJump { pc: 0, target: i64::MIN as i128 }, 0), run(vec![Instr::Jmp(i64::MIN)]));
+        assert_eq!((ExitResult::Terminated, 1), run(vec![Instr::Jmp(1)]));
+        assert_eq!(Err(Fault::InvalidJump { target: usize::MAX as i128 + 1 }), State { pc: usize::MAX, ..State::default() }.jump(1));
+    }
+
+    #[test]
+    fn overflow_modes() {
+        let run = |text: &str, overflow| {
+            let mut program: Program = text.parse().unwrap();
+            program.set_overflow(overflow);
+            (program.run_to_completion(), program.state().acc)
+        };
+
+        let add = "acc +9223372036854775807\nacc +1\n";
+        assert_eq!((ExitResult::Overflow { pc: 1 }, i64::MAX), run(add, Overflow::Trap));
+        assert_eq!((ExitResult::Terminated, i64::MIN), run(add, Overflow::Wrap));
+        assert_eq!((ExitResult::Terminated, i64::MAX), run(add, Overflow::Saturate));
+
+        let mul = "acc -4611686018427387905\ntpl acc\n";
+        assert_eq!((ExitResult::Overflow { pc: 1 }, -4611686018427387905), run(mul, Overflow::Trap));
+        assert_eq!((ExitResult::Terminated, i64::MIN), run(mul, Overflow::Saturate));
+        assert_eq!((ExitResult::Terminated, -4611686018427387905i64.wrapping_mul(3)), run(mul, Overflow::Wrap));
+
+        assert_eq!(Ok(Overflow::Saturate), "saturate".parse::<Overflow>().map_err(|e| e.kind()));
+        assert!("clamp".parse::<Overflow>().is_err());
+    }
+}
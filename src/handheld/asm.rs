@@ -0,0 +1,222 @@
+//! A friendlier way to write handheld programs: `name:` labels, `jmp name`
+//! instead of counted offsets, `;` comments and blank lines.
+//!
+//! ```text
+//! start:  acc +1      ; count
+//!         jnz a, done
+//!         jmp start
+//! done:
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use super::{parse, Instr};
+use super::opcodes::Registry;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    InvalidInstruction(String),
+}
+
+/// What went wrong on which line, 1-based
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            AsmErrorKind::InvalidLabel(label) => write!(f, "line {}: invalid label '{}'", self.line, label),
+            AsmErrorKind::DuplicateLabel(label) => write!(f, "line {}: label '{}' is already defined", self.line, label),
+            AsmErrorKind::UndefinedLabel(label) => write!(f, "line {}: undefined label '{}'", self.line, label),
+            AsmErrorKind::InvalidInstruction(text) => write!(f, "line {}: invalid instruction '{}'", self.line, text),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits an instruction into its mnemonic and operands, after any
+/// whitespace following the mnemonic
+fn split(text: &str) -> (&str, Vec<&str>) {
+    match text.split_once(char::is_whitespace) {
+        Some((name, operands)) => (name, operands.split(',').map(str::trim).collect()),
+        None => (text, vec![]),
+    }
+}
+
+/// Which operand of `name` is a relative jump that may be written as a label
+fn branch_operand(name: &str, registry: &Registry) -> Option<usize> {
+    match name {
+        "jmp" | "nop" => Some(0),
+        _ => registry.by_name(name).and_then(|opcode| opcode.branch),
+    }
+}
+
+/// Turns assembly into instructions, resolving labels to relative offsets.
+/// Labels work for `jmp`, `nop` and the branch operand of extended opcodes.
+pub fn assemble(text: &str, registry: &Registry) -> Result<Vec<Instr>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut lines = vec![];
+
+    for (n, line) in text.lines().enumerate() {
+        let error = |kind| AsmError { line: n + 1, kind };
+        let mut rest = line.split(';').next().unwrap_or_default().trim();
+        while let Some((label, after)) = rest.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                return Err(error(AsmErrorKind::InvalidLabel(label.to_string())))
+            }
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(error(AsmErrorKind::DuplicateLabel(label.to_string())))
+            }
+            rest = after.trim();
+        }
+
+        if !rest.is_empty() {
+            lines.push((n + 1, rest));
+        }
+    }
+
+    lines.iter().enumerate()
+        .map(|(pc, (line, text))| {
+            let error = |kind| AsmError { line: *line, kind };
+            let (name, mut operands) = split(text);
+            let resolved;
+            if let Some(target) = branch_operand(name, registry).and_then(|i| operands.get_mut(i)) {
+                if is_label(target) {
+                    let address = labels.get(target).ok_or_else(|| error(AsmErrorKind::UndefinedLabel(target.to_string())))?;
+                    resolved = format!("{:+}", *address as i64 - pc as i64);
+                    *target = &resolved;
+                }
+            }
+
+            let text = match operands.is_empty() {
+                true => name.to_string(),
+                false => format!("{} {}", name, operands.join(", ")),
+            };
+            parse::instr(&text, registry).ok_or_else(|| error(AsmErrorKind::InvalidInstruction(text.to_string())))
+        })
+        .collect()
+}
+
+/// Which operand of `instr` is a jump it may take, and the offset
+fn jump(instr: &Instr) -> Option<(usize, i64)> {
+    match instr {
+        Instr::Jmp(offset) => Some((0, *offset)),
+        Instr::Op(op) => op.opcode().branch.map(|i| (i, op.args()[i])),
+        Instr::Nop(_) | Instr::Acc(_) => None,
+    }
+}
+
+/// Where `instr` at `pc` may jump to, if that is inside the program or just
+/// past its end
+fn target(pc: usize, instr: &Instr, len: usize) -> Option<usize> {
+    let (_, offset) = jump(instr)?;
+    (pc as i64).checked_add(offset).filter(|target| (0..=len as i64).contains(target)).map(|target| target as usize)
+}
+
+/// The reverse of `assemble`, naming every jump target `L<pc>`
+pub fn disassemble(code: &[Instr]) -> String {
+    let targets = code.iter().enumerate()
+        .filter_map(|(pc, instr)| target(pc, instr, code.len()))
+        .collect::<BTreeSet<_>>();
+
+    let mut out = String::new();
+    for (pc, instr) in code.iter().enumerate() {
+        if targets.contains(&pc) {
+            out.push_str(&format!("L{}:\n", pc));
+        }
+
+        let text = instr.to_string();
+        let (name, mut operands) = split(&text);
+        let label = target(pc, instr, code.len()).map(|target| format!("L{}", target));
+        if let (Some(label), Some((i, _))) = (&label, jump(instr)) {
+            operands[i] = label;
+        }
+        out.push_str(&format!("    {} {}\n", name, operands.join(", ")));
+    }
+
+    if targets.contains(&code.len()) {
+        out.push_str(&format!("L{}:\n", code.len()));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::{Program, EXAMPLE};
+
+    #[test]
+    fn assembles_labels() {
+        let text = "
+; counts down from 3
+        acc +3
+loop:   jz acc, done    ; finished?
+        acc -1
+        out acc
+        jmp loop
+done:
+";
+        let registry = Registry::default();
+        let code = assemble(text, &registry).unwrap();
+        assert_eq!("acc +3\njz acc, +4\nacc -1\nout acc\njmp -3\n", code.iter().map(|i| format!("{}\n", i)).collect::<String>());
+
+        let mut program = Program::new(code);
+        while program.step().is_ok() {}
+        assert_eq!(vec![2, 1, 0], program.state().output);
+    }
+
+    #[test]
+    fn assembles_tabs() {
+        let registry = Registry::default();
+        let code = assemble("loop:\tacc\t+1\n\tjz  \tacc,\tloop\n\tjmp\tloop\n", &registry).unwrap();
+        assert_eq!("acc +1\njz acc, -1\njmp -2\n", code.iter().map(|i| format!("{}\n", i)).collect::<String>());
+    }
+
+    #[test]
+    fn assembly_errors() {
+        let registry = Registry::default();
+        let kind = |text| assemble(text, &registry).unwrap_err();
+        assert_eq!(AsmError { line: 2, kind: AsmErrorKind::UndefinedLabel("nowhere".to_string()) }, kind("acc +1\njmp nowhere\n"));
+        assert_eq!(AsmError { line: 3, kind: AsmErrorKind::DuplicateLabel("a".to_string()) }, kind("a: nop +0\n\na:\n"));
+        assert_eq!(AsmError { line: 1, kind: AsmErrorKind::InvalidLabel("1st".to_string()) }, kind("1st: nop +0\n"));
+        assert_eq!(AsmError { line: 1, kind: AsmErrorKind::InvalidInstruction("acc loop".to_string()) }, kind("loop: acc loop\n"));
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let program: Program = EXAMPLE.parse().unwrap();
+        let text = disassemble(&program.code());
+        assert_eq!("    nop +0
+L1:
+    acc +1
+    jmp L6
+L3:
+    acc +3
+    jmp L1
+    acc -99
+L6:
+    acc +1
+    jmp L3
+    acc +6
+", text);
+        assert_eq!(program.code(), &assemble(&text, &Registry::default()).unwrap()[..]);
+
+        let code = assemble("jmp +5\njnz a, end\nnop +0\nend:\n", &Registry::default()).unwrap();
+        assert_eq!("    jmp +5\n    jnz a, L3\n    nop +0\nL3:\n", disassemble(&code));
+        assert_eq!(code, assemble(&disassemble(&code), &Registry::default()).unwrap());
+    }
+}
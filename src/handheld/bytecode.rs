@@ -0,0 +1,107 @@
+//! A binary form of handheld programs that loads without parsing text:
+//! `MAGIC`, the instruction count as a varint, then every instruction as
+//! written by `Instr::write`.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use super::{invalid_data, varint, Instr, Program};
+use super::opcodes::Registry;
+
+/// Starts every bytecode file; the last byte is the format version
+pub const MAGIC: &[u8; 4] = b"HHB\x01";
+
+pub fn write_program<W: Write>(code: &[Instr], mut out: W) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    varint::write_u64(&mut out, code.len() as u64)?;
+    code.iter().try_for_each(|instr| instr.write(&mut out))
+}
+
+/// Reads bytecode, looking extended opcodes up in `registry`
+pub fn read_program<R: Read>(mut input: R, registry: &Registry) -> io::Result<Vec<Instr>> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if magic[..3] != MAGIC[..3] {
+        return Err(invalid_data("not handheld bytecode".to_string()))
+    }
+    if magic[3] != MAGIC[3] {
+        return Err(invalid_data(format!("unsupported bytecode version {}, expected {}", magic[3], MAGIC[3])))
+    }
+
+    let len = varint::read_u64(&mut input)? as usize;
+    // The count comes from the file, so don't trust it with a huge allocation
+    let mut code = Vec::with_capacity(len.min(1 << 20));
+    for _ in 0..len {
+        code.push(Instr::read(&mut input, registry)?);
+    }
+
+    if input.read(&mut [0])? != 0 {
+        return Err(invalid_data("trailing bytes after the last instruction".to_string()))
+    }
+    Ok(code)
+}
+
+/// Loads a program from a file holding either bytecode or text
+pub fn load<P: AsRef<Path>>(path: P, registry: &Registry) -> io::Result<Program> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(&MAGIC[..3]) {
+        return read_program(&bytes[..], registry).map(Program::new)
+    }
+
+    let text = String::from_utf8(bytes).map_err(|_| invalid_data("neither bytecode nor text".to_string()))?;
+    Program::parse_with(&text, registry).map_err(|e| invalid_data(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::EXAMPLE;
+
+    fn compile(code: &[Instr]) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_program(code, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trips_text() {
+        let registry = Registry::default();
+        for text in [EXAMPLE, "acc +3\nmul acc, -200\nout acc\njnz a, -3\nhlf d\n", "acc -9223372036854775808\njmp +9223372036854775807\nnop -9223372036854775808\n"].iter() {
+            let program = Program::parse_with(text, &registry).unwrap();
+            let bytes = compile(&program.code());
+            let code = read_program(&bytes[..], &registry).unwrap();
+            assert_eq!(*text, code.iter().map(|i| format!("{}\n", i)).collect::<String>());
+        }
+
+        assert_eq!(4 + 1 + 9 * 2 + 1, compile(&EXAMPLE.parse::<Program>().unwrap().code()).len());
+    }
+
+    #[test]
+    fn round_trips_large_programs() {
+        let code = (0..100_000i64)
+            .map(|i| match i % 3 {
+                0 => Instr::Nop(i),
+                1 => Instr::Acc(-i * 1000),
+                _ => Instr::Jmp(i64::MIN + i),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(code, read_program(&compile(&code)[..], &Registry::default()).unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_bytecode() {
+        let registry = Registry::default();
+        let mut bytes = compile(&EXAMPLE.parse::<Program>().unwrap().code());
+
+        assert!(read_program(&bytes[..bytes.len() - 1], &registry).is_err());
+        assert!(read_program(&b"HHX\x01\x00"[..], &registry).is_err());
+        assert!(read_program(&b"HHB\x02\x00"[..], &registry).is_err());
+        assert!(read_program(&b"HHB\x01\x01\x09\x00"[..], &registry).is_err());
+        assert!(read_program(&b"HHB\x01\x01\x10\x09"[..], &registry).is_err());
+        assert_eq!(Ok(vec![]), read_program(&b"HHB\x01\x00"[..], &registry).map_err(|e| e.kind()));
+
+        bytes.push(0);
+        assert!(read_program(&bytes[..], &registry).is_err());
+    }
+}
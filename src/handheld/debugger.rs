@@ -0,0 +1,280 @@
+//! An interactive debugger for handheld programs. Commands are read one per
+//! line so a session can be scripted as easily as typed.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use super::{ExitResult, Program, State};
+
+/// How many instructions `list` shows on each side of the current one
+const LIST_RADIUS: usize = 3;
+
+/// Why `continue` handed control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(usize),
+    Watch { old: i64, new: i64 },
+    Exit(ExitResult),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(pc) => write!(f, "breakpoint at {}", pc),
+            Stop::Watch { old, new } => write!(f, "acc changed from {} to {}", old, new),
+            Stop::Exit(exit) => write_exit(f, exit),
+        }
+    }
+}
+
+fn write_exit(f: &mut fmt::Formatter, exit: &ExitResult) -> fmt::Result {
+    match exit {
+        ExitResult::Terminated => write!(f, "program terminated"),
+        ExitResult::InfiniteLoop { pc } => write!(f, "instruction {} is about to run a second time", pc),
+        ExitResult::OutOfBounds { pc } => write!(f, "pc {} is outside the program", pc),
+        ExitResult::InvalidJump { pc, target } => write!(f, "instruction {} jumps to {}, outside the program", pc, target),
+        ExitResult::Overflow { pc } => write!(f, "instruction {} overflowed", pc),
+        ExitResult::StepLimit => write!(f, "step limit reached"),
+    }
+}
+
+/// A program under the debugger, along with every state it went through
+pub struct Debugger {
+    program: Program,
+    history: Vec<State>,
+    hits: Vec<usize>,
+    breakpoints: BTreeSet<usize>,
+    watch_acc: bool,
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Self {
+        let hits = vec![0; program.len()];
+        Debugger { program, history: vec![], hits, breakpoints: BTreeSet::new(), watch_acc: false }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Runs one instruction, remembering the state before it
+    pub fn step(&mut self) -> Result<(), ExitResult> {
+        let before = self.program.state().clone();
+        self.program.step()?;
+        self.hits[before.pc] += 1;
+        self.history.push(before);
+        Ok(())
+    }
+
+    /// Undoes the last instruction, returning false at the start of history
+    pub fn back(&mut self) -> bool {
+        match self.history.pop() {
+            Some(state) => {
+                self.hits[state.pc] -= 1;
+                *self.program.state_mut() = state;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Runs until a breakpoint, a watched change, or the program stopping.
+    /// An instruction that already ran counts as an infinite loop.
+    pub fn cont(&mut self) -> Stop {
+        loop {
+            if let Some(exit) = self.program.exit() {
+                return Stop::Exit(exit)
+            }
+
+            let pc = self.program.state().pc;
+            if self.hits[pc] > 0 {
+                return Stop::Exit(ExitResult::InfiniteLoop { pc })
+            }
+
+            let old = self.program.state().acc;
+            if let Err(exit) = self.step() {
+                return Stop::Exit(exit)
+            }
+
+            let state = self.program.state();
+            if self.watch_acc && state.acc != old {
+                return Stop::Watch { old, new: state.acc }
+            }
+
+            if self.breakpoints.contains(&state.pc) {
+                return Stop::Breakpoint(state.pc)
+            }
+        }
+    }
+
+    /// The instructions within `radius` of `pc`, marking the current one
+    /// with `>` and breakpoints with `*`
+    pub fn disassemble(&self, pc: usize, radius: usize) -> String {
+        let code = self.program.code();
+        let current = self.program.state().pc;
+        let end = pc.saturating_add(radius).saturating_add(1).min(code.len());
+
+        (pc.saturating_sub(radius)..end)
+            .map(|i| format!(
+                "{}{} {:>4}  {}\n",
+                if i == current { '>' } else { ' ' },
+                if self.breakpoints.contains(&i) { '*' } else { ' ' },
+                i,
+                code[i],
+            ))
+            .collect()
+    }
+
+    fn location(&self) -> String {
+        let state = self.program.state();
+        match self.program.next_instr() {
+            Some(instr) => format!("pc {}, acc {}: {}\n", state.pc, state.acc, instr),
+            None => format!("pc {}, acc {}: outside the program\n", state.pc, state.acc),
+        }
+    }
+
+    /// Runs a single command and returns what it printed
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        let words = command.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            [] => Ok(String::new()),
+            ["step"] | ["s"] => self.execute("step 1"),
+            ["step", n] | ["s", n] => {
+                let n = n.parse::<usize>().map_err(|_| format!("invalid step count '{}'", n))?;
+                for _ in 0..n {
+                    if let Err(exit) = self.step() {
+                        return Ok(format!("{}\n{}", Stop::Exit(exit), self.location()))
+                    }
+                }
+                Ok(self.location())
+            },
+            ["continue"] | ["c"] => {
+                let stop = self.cont();
+                Ok(format!("{}\n{}", stop, self.location()))
+            },
+            ["back"] | ["r"] => match self.back() {
+                true => Ok(self.location()),
+                false => Err("already at the start of the history".to_string()),
+            },
+            ["break"] | ["b"] => Ok(self.breakpoints.iter().map(|pc| format!("breakpoint at {}\n", pc)).collect()),
+            ["break", pc] | ["b", pc] => {
+                let pc = pc.parse::<usize>().map_err(|_| format!("invalid pc '{}'", pc))?;
+                if pc >= self.program.len() {
+                    return Err(format!("pc {} is outside the program", pc))
+                }
+                match self.breakpoints.insert(pc) {
+                    true => Ok(format!("breakpoint set at {}\n", pc)),
+                    false => {
+                        self.breakpoints.remove(&pc);
+                        Ok(format!("breakpoint cleared at {}\n", pc))
+                    },
+                }
+            },
+            ["watch", "acc"] | ["w", "acc"] => {
+                self.watch_acc = !self.watch_acc;
+                Ok(format!("watching acc: {}\n", if self.watch_acc { "on" } else { "off" }))
+            },
+            ["print"] | ["p"] => Ok(self.location()),
+            ["list"] | ["l"] => Ok(self.disassemble(self.program.state().pc, LIST_RADIUS)),
+            ["list", pc] | ["l", pc] => {
+                let pc = pc.parse::<usize>().map_err(|_| format!("invalid pc '{}'", pc))?;
+                if pc >= self.program.len() {
+                    return Err(format!("pc {} is outside the program", pc))
+                }
+                Ok(self.disassemble(pc, LIST_RADIUS))
+            },
+            _ => Err(format!("unknown command '{}'", command.trim())),
+        }
+    }
+
+    /// Reads commands from `input` until it ends or says `quit`, writing
+    /// their output and any errors to `output`
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if let "quit" | "q" = line.trim() {
+                break
+            }
+
+            match self.execute(&line) {
+                Ok(out) => write!(output, "{}", out)?,
+                Err(e) => writeln!(output, "error: {}", e)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::EXAMPLE;
+
+    fn debugger() -> Debugger {
+        Debugger::new(EXAMPLE.parse().unwrap())
+    }
+
+    #[test]
+    fn step_and_back() {
+        let mut debugger = debugger();
+        assert_eq!(Ok("pc 2, acc 1: jmp +4\n".to_string()), debugger.execute("step 2"));
+        assert_eq!(Ok("pc 1, acc 0: acc +1\n".to_string()), debugger.execute("back"));
+        assert_eq!(Ok("pc 0, acc 0: nop +0\n".to_string()), debugger.execute("r"));
+        assert!(debugger.execute("back").is_err());
+        assert!(debugger.execute("step x").is_err());
+        assert!(debugger.execute("jump").is_err());
+    }
+
+    #[test]
+    fn continue_stops() {
+        let mut debugger = debugger();
+        assert_eq!(Stop::Exit(ExitResult::InfiniteLoop { pc: 1 }), debugger.cont());
+        assert_eq!(5, debugger.program().state().acc);
+
+        while debugger.back() {}
+        debugger.execute("break 6").unwrap();
+        assert_eq!(Stop::Breakpoint(6), debugger.cont());
+
+        debugger.execute("watch acc").unwrap();
+        assert_eq!(Stop::Watch { old: 1, new: 2 }, debugger.cont());
+        assert_eq!(Stop::Watch { old: 2, new: 5 }, debugger.cont());
+        assert_eq!(Stop::Exit(ExitResult::InfiniteLoop { pc: 1 }), debugger.cont());
+    }
+
+    #[test]
+    fn disassembly() {
+        let mut debugger = debugger();
+        debugger.execute("b 4").unwrap();
+        debugger.execute("s 3").unwrap();
+        assert_eq!(concat!(
+            "      3  acc +3\n",
+            " *    4  jmp -3\n",
+            "      5  acc -99\n",
+            ">     6  acc +1\n",
+            "      7  jmp -4\n",
+            "      8  acc +6\n",
+        ), debugger.execute("list").unwrap());
+        assert_eq!("      0  nop +0\n      1  acc +1\n", debugger.disassemble(0, 1));
+        assert_eq!("", debugger.disassemble(usize::MAX, LIST_RADIUS));
+        assert!(debugger.execute("list 9").is_err());
+        assert!(debugger.execute("list 18446744073709551615").is_err());
+    }
+
+    #[test]
+    fn scripted_session() {
+        let script = "break 4\ncontinue\nprint\nbreak 4\ncontinue\nquit\nstep\n";
+        let mut out = vec![];
+        debugger().run(script.as_bytes(), &mut out).unwrap();
+
+        assert_eq!("breakpoint set at 4
+breakpoint at 4
+pc 4, acc 5: jmp -3
+pc 4, acc 5: jmp -3
+breakpoint cleared at 4
+instruction 1 is about to run a second time
+pc 1, acc 5: acc +1
+", String::from_utf8(out).unwrap());
+    }
+}
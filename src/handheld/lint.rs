@@ -0,0 +1,385 @@
+//! Problems that can be found in a handheld program without running it, by
+//! looking at its control-flow graph.
+
+use std::fmt;
+
+use super::Instr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// Instructions `start` to `end`, inclusive, can never run
+    Unreachable { start: usize, end: usize },
+    /// Once execution reaches `entry` it can never leave `body`
+    InfiniteLoop { entry: usize, body: Vec<usize> },
+    /// A `jmp +0`
+    SelfLoop { pc: usize },
+    /// A jump before the start or past the end of the program
+    JumpOutOfBounds { pc: usize, target: i128 },
+    /// A `nop` that would jump out of bounds or into an infinite loop if it
+    /// were flipped to a `jmp`
+    DangerousNop { pc: usize, target: i128 },
+}
+
+impl Finding {
+    /// The first instruction the finding is about
+    pub fn pc(&self) -> usize {
+        match self {
+            Finding::Unreachable { start, .. } => *start,
+            Finding::InfiniteLoop { entry, .. } => *entry,
+            Finding::SelfLoop { pc } | Finding::JumpOutOfBounds { pc, .. } | Finding::DangerousNop { pc, .. } => *pc,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::Unreachable { start, end } if start == end => write!(f, "{}: unreachable", start),
+            Finding::Unreachable { start, end } => write!(f, "{}-{}: unreachable", start, end),
+            Finding::InfiniteLoop { entry, body } => write!(
+                f,
+                "{}: guaranteed infinite loop through {}",
+                entry,
+                body.iter().map(|pc| pc.to_string()).collect::<Vec<_>>().join(" "),
+            ),
+            Finding::SelfLoop { pc } => write!(f, "{}: jmp +0 loops on itself", pc),
+            Finding::JumpOutOfBounds { pc, target } => write!(f, "{}: jumps to {}, outside the program", pc, target),
+            Finding::DangerousNop { pc, target } => write!(f, "{}: flipping this nop would jump to {}, where the program cannot terminate", pc, target),
+        }
+    }
+}
+
+/// Where control may go after `instr` at `pc`, whether or not that is
+/// inside the program
+fn targets(pc: usize, instr: &Instr) -> Vec<i128> {
+    let next = pc as i128 + 1;
+    let jump = |offset: i64| pc as i128 + offset as i128;
+    match instr {
+        Instr::Nop(_) | Instr::Acc(_) => vec![next],
+        Instr::Jmp(offset) => vec![jump(*offset)],
+        Instr::Op(op) => match op.opcode().branch {
+            Some(i) => vec![next, jump(op.args()[i])],
+            None => vec![next],
+        },
+    }
+}
+
+/// Marks, on top of `marked`, every instruction from which a marked one can
+/// be reached
+fn reaching(predecessors: &[Vec<usize>], mut marked: Vec<bool>) -> Vec<bool> {
+    let mut queue = (0..marked.len()).filter(|pc| marked[*pc]).collect::<Vec<_>>();
+    while let Some(pc) = queue.pop() {
+        for &previous in &predecessors[pc] {
+            if !std::mem::replace(&mut marked[previous], true) {
+                queue.push(previous);
+            }
+        }
+    }
+    marked
+}
+
+/// Which instructions every way from an instruction to the end of the
+/// program has to go through. Position `len` stands for the end itself.
+struct PostDominators {
+    /// The range of depth-first numbers covering each instruction's subtree
+    /// in the post-dominator tree, or `None` if it cannot reach the end
+    span: Vec<Option<(usize, usize)>>,
+}
+
+impl PostDominators {
+    /// Builds the tree with Cooper, Harvey and Kennedy's iterative algorithm
+    fn new(successors: &[Vec<i128>]) -> Self {
+        let end = successors.len();
+        let valid = |target: &&i128| (0..=end as i128).contains(*target);
+        let mut predecessors = vec![vec![]; end + 1];
+        for (pc, targets) in successors.iter().enumerate() {
+            targets.iter().filter(valid).for_each(|target| predecessors[*target as usize].push(pc));
+        }
+
+        // Postorder of a depth-first search backwards from the end
+        let mut number = vec![None; end + 1];
+        let mut order = vec![];
+        let mut seen = vec![false; end + 1];
+        seen[end] = true;
+        let mut stack = vec![(end, 0)];
+        while let Some((pc, i)) = stack.pop() {
+            match predecessors[pc].get(i) {
+                Some(&previous) => {
+                    stack.push((pc, i + 1));
+                    if !std::mem::replace(&mut seen[previous], true) {
+                        stack.push((previous, 0));
+                    }
+                },
+                None => {
+                    number[pc] = Some(order.len());
+                    order.push(pc);
+                },
+            }
+        }
+
+        let mut parent = vec![None; end + 1];
+        parent[end] = Some(end);
+        let intersect = |parent: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while number[a] < number[b] {
+                    a = parent[a].expect("processed instructions have a parent");
+                }
+                while number[b] < number[a] {
+                    b = parent[b].expect("processed instructions have a parent");
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &pc in order.iter().rev().skip(1) {
+                let mut new = None;
+                for target in successors[pc].iter().filter(valid).map(|target| *target as usize) {
+                    if parent[target].is_some() {
+                        new = Some(new.map_or(target, |new| intersect(&parent, new, target)));
+                    }
+                }
+                if new != parent[pc] {
+                    parent[pc] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![vec![]; end + 1];
+        (0..end).for_each(|pc| if let Some(p) = parent[pc] { children[p].push(pc) });
+        let mut span = vec![None; end + 1];
+        let mut enter = vec![0; end + 1];
+        let mut next = 0;
+        let mut stack = vec![(end, false)];
+        while let Some((pc, leaving)) = stack.pop() {
+            match leaving {
+                true => span[pc] = Some((enter[pc], next)),
+                false => {
+                    enter[pc] = next;
+                    next += 1;
+                    stack.push((pc, true));
+                    stack.extend(children[pc].iter().map(|child| (*child, false)));
+                },
+            }
+        }
+
+        PostDominators { span }
+    }
+
+    /// Whether `pc` can reach the end without going through `avoid`
+    fn terminates_avoiding(&self, pc: usize, avoid: usize) -> bool {
+        match (self.span[pc], self.span[avoid]) {
+            (Some(_), None) => true,
+            (Some((start, stop)), Some((avoid_start, avoid_stop))) => start < avoid_start || stop > avoid_stop,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Reports every problem found in `code`, ordered by instruction
+pub fn lint(code: &[Instr]) -> Vec<Finding> {
+    let len = code.len();
+    let inside = |target: i128| (0..len as i128).contains(&target);
+    let successors = code.iter().enumerate()
+        .map(|(pc, instr)| targets(pc, instr))
+        .collect::<Vec<_>>();
+
+    let mut reachable = vec![false; len];
+    let mut queue = if len > 0 { vec![0] } else { vec![] };
+    while let Some(pc) = queue.pop() {
+        if !std::mem::replace(&mut reachable[pc], true) {
+            queue.extend(successors[pc].iter().filter(|t| inside(**t)).map(|t| *t as usize));
+        }
+    }
+
+    // Instructions that can get out of the program, by terminating or by
+    // jumping somewhere invalid
+    let mut predecessors = vec![vec![]; len];
+    let mut escapes = vec![false; len];
+    for (pc, targets) in successors.iter().enumerate() {
+        for target in targets {
+            match inside(*target) {
+                true => predecessors[*target as usize].push(pc),
+                false => escapes[pc] = true,
+            }
+        }
+    }
+    let escapes = reaching(&predecessors, escapes);
+    let post_dominators = PostDominators::new(&successors);
+
+    let mut findings = vec![];
+
+    let mut start = None;
+    for (pc, unreachable) in reachable.iter().map(|r| !r).chain(Some(false)).enumerate() {
+        match (start, unreachable) {
+            (None, true) => start = Some(pc),
+            (Some(s), false) => {
+                findings.push(Finding::Unreachable { start: s, end: pc - 1 });
+                start = None;
+            },
+            _ => {},
+        }
+    }
+
+    let trapped = |pc: usize| reachable[pc] && !escapes[pc];
+    let entries = (0..len).filter(|pc| trapped(*pc)).filter(|pc| {
+        *pc == 0 || predecessors[*pc].iter().any(|previous| reachable[*previous] && !trapped(*previous))
+    });
+    // Each walk follows trapped instructions until it comes back to its own
+    // path, which is a new loop, or to an earlier walk, whose loop is
+    // already reported
+    let mut position = vec![None; len];
+    let mut walked = vec![false; len];
+    for entry in entries {
+        let mut path = vec![];
+        let mut pc = entry;
+        let body = loop {
+            if walked[pc] {
+                break None
+            }
+            if let Some(start) = position[pc] {
+                break Some(path[start..].to_vec())
+            }
+            position[pc] = Some(path.len());
+            path.push(pc);
+            pc = successors[pc].iter().map(|t| *t as usize).find(|t| trapped(*t)).expect("trapped instructions only lead to trapped ones");
+        };
+
+        path.iter().for_each(|pc| walked[*pc] = true);
+        if let Some(body) = body {
+            findings.push(Finding::InfiniteLoop { entry, body });
+        }
+    }
+
+    for (pc, instr) in code.iter().enumerate() {
+        match instr {
+            Instr::Jmp(0) => findings.push(Finding::SelfLoop { pc }),
+            Instr::Nop(offset) if *offset != 0 => {
+                // Once flipped, any way back through this nop loops, so the
+                // jump only helps if its target can terminate without it
+                let target = pc as i128 + *offset as i128;
+                if target != len as i128 && !(inside(target) && post_dominators.terminates_avoiding(target as usize, pc)) {
+                    findings.push(Finding::DangerousNop { pc, target });
+                }
+            },
+            _ => {},
+        }
+
+        let out_of_bounds = successors[pc].iter().filter(|t| !inside(**t) && **t != len as i128);
+        findings.extend(out_of_bounds.map(|target| Finding::JumpOutOfBounds { pc, target: *target }));
+    }
+
+    findings.sort_by_key(Finding::pc);
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::{Program, EXAMPLE};
+
+    fn lint_text(text: &str) -> Vec<Finding> {
+        lint(&text.parse::<Program>().unwrap().code())
+    }
+
+    #[test]
+    fn lints_example() {
+        assert_eq!(vec![
+            Finding::InfiniteLoop { entry: 0, body: vec![1, 2, 6, 7, 3, 4] },
+            Finding::Unreachable { start: 5, end: 5 },
+            Finding::Unreachable { start: 8, end: 8 },
+        ], lint_text(EXAMPLE));
+
+        let mut program: Program = EXAMPLE.parse().unwrap();
+        program.patch(7, Instr::Nop(-4));
+        // Flipping the repaired nop back would bring the original loop back
+        assert_eq!(vec![
+            Finding::Unreachable { start: 3, end: 5 },
+            Finding::DangerousNop { pc: 7, target: 3 },
+        ], lint(&program.code()));
+    }
+
+    #[test]
+    fn lints_jumps() {
+        let findings = lint_text("nop +9\njz a, +3\njmp -5\nacc +2\nacc +2\nnop -1\njmp +0\nacc +1\n");
+        assert_eq!(vec![
+            Finding::DangerousNop { pc: 0, target: 9 },
+            Finding::JumpOutOfBounds { pc: 2, target: -3 },
+            Finding::Unreachable { start: 3, end: 3 },
+            Finding::InfiniteLoop { entry: 4, body: vec![6] },
+            Finding::DangerousNop { pc: 5, target: 4 },
+            Finding::SelfLoop { pc: 6 },
+            Finding::Unreachable { start: 7, end: 7 },
+        ], findings);
+        assert_eq!("3: unreachable", findings[2].to_string());
+        assert_eq!("4: guaranteed infinite loop through 6", findings[3].to_string());
+        assert_eq!("1-2: unreachable", Finding::Unreachable { start: 1, end: 2 }.to_string());
+
+        assert_eq!(Vec::<Finding>::new(), lint_text("nop +2\njnz a, -1\nacc +1\n"));
+        assert_eq!(vec![
+            Finding::DangerousNop { pc: 0, target: 2 },
+            Finding::JumpOutOfBounds { pc: 2, target: -8 },
+        ], lint_text("nop +2\nacc +1\njmp -10\n"));
+
+        // The flipped jump's only way to the end leads back through it
+        assert_eq!(vec![
+            Finding::DangerousNop { pc: 1, target: 3 },
+            Finding::Unreachable { start: 3, end: 4 },
+        ], lint_text("nop +0\nnop +2\njmp +3\njmp -2\nnop +0\n"));
+        // Here the jz offers a way to the end that avoids the nop
+        assert_eq!(Vec::<Finding>::new(), lint_text("jz a, +3\nnop +1\njmp -2\nacc +1\n"));
+        assert_eq!(Vec::<Finding>::new(), lint(&[]));
+    }
+
+    #[test]
+    fn dangerous_nops_match_search() {
+        let mut seed = 5u64;
+        let mut random = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+
+        for _ in 0..300 {
+            let len = 1 + random(10) as i64;
+            let text = (0..len)
+                .map(|_| {
+                    let operand = random(2 * len as u64 + 3) as i64 - len - 1;
+                    match random(4) {
+                        0 | 1 => format!("nop {:+}", operand),
+                        2 => format!("jmp {:+}", operand),
+                        _ => format!("jz a, {:+}", operand),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let code = text.parse::<Program>().unwrap().code().to_vec();
+
+            let expected = code.iter().enumerate()
+                .filter_map(|(pc, instr)| match instr {
+                    Instr::Nop(offset) if *offset != 0 => Some((pc, pc as i128 + *offset as i128)),
+                    _ => None,
+                })
+                .filter(|(pc, target)| {
+                    let mut flipped = code.clone();
+                    flipped[*pc] = Instr::Jmp(*target as i64 - *pc as i64);
+                    let mut seen = vec![false; code.len()];
+                    let mut queue = vec![*target];
+                    let mut terminates = false;
+                    while let Some(at) = queue.pop() {
+                        if at == code.len() as i128 {
+                            terminates = true;
+                        } else if (0..code.len() as i128).contains(&at) && !std::mem::replace(&mut seen[at as usize], true) {
+                            queue.extend(targets(at as usize, &flipped[at as usize]));
+                        }
+                    }
+                    !terminates
+                })
+                .map(|(pc, target)| Finding::DangerousNop { pc, target })
+                .collect::<Vec<_>>();
+            let found = lint(&code).into_iter().filter(|f| matches!(f, Finding::DangerousNop { .. })).collect::<Vec<_>>();
+            assert_eq!(expected, found, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Instructions beyond day 8's `nop`, `acc` and `jmp`. Each one is an
+//! `Opcode` that holds its name, binary code, operand layout and semantics,
+//! so adding an instruction means writing one `Opcode` and registering it.
+
+use std::fmt;
+
+use super::{Fault, Overflow, State};
+
+/// Register names, indexed the way `Register` operands are
+pub const REGISTERS: [&str; 5] = ["acc", "a", "b", "c", "d"];
+
+/// Codes below this are the three core instructions
+pub const FIRST_EXTENDED: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// One of `REGISTERS`, stored as its index
+    Register,
+    /// A signed number, with or without a sign
+    Immediate,
+}
+
+/// An extended instruction
+#[derive(Debug)]
+pub struct Opcode {
+    pub name: &'static str,
+    pub code: u8,
+    pub operands: &'static [OperandKind],
+    /// Which operand, if any, is a relative jump the instruction may take.
+    /// Static analysis uses this; `exec` still does the jumping.
+    pub branch: Option<usize>,
+    /// Runs the instruction, which must also move the program counter and
+    /// do arithmetic through the `Overflow` it is given. A fault must leave
+    /// the state untouched.
+    pub exec: fn(&[i64; 2], &mut State, Overflow) -> Result<(), Fault>,
+}
+
+/// An extended instruction with its operands, every register operand
+/// naming one of `REGISTERS`
+#[derive(Debug, Clone, Copy)]
+pub struct Op {
+    opcode: &'static Opcode,
+    args: [i64; 2],
+}
+
+impl Op {
+    /// `None` if a register operand is out of range. Arguments beyond the
+    /// operands `opcode` takes are ignored.
+    pub fn new(opcode: &'static Opcode, args: [i64; 2]) -> Option<Op> {
+        let mut checked = [0; 2];
+        for ((kind, arg), slot) in opcode.operands.iter().zip(args.iter()).zip(checked.iter_mut()) {
+            if *kind == OperandKind::Register && !(0..REGISTERS.len() as i64).contains(arg) {
+                return None
+            }
+            *slot = *arg;
+        }
+        Some(Op { opcode, args: checked })
+    }
+
+    pub fn opcode(&self) -> &'static Opcode {
+        self.opcode
+    }
+
+    pub fn args(&self) -> &[i64; 2] {
+        &self.args
+    }
+}
+
+impl PartialEq for Op {
+    fn eq(&self, other: &Self) -> bool {
+        self.opcode.code == other.opcode.code && self.args == other.args
+    }
+}
+
+impl Eq for Op {}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode.name)?;
+        for (i, (kind, arg)) in self.opcode.operands.iter().zip(self.args.iter()).enumerate() {
+            write!(f, "{}", if i == 0 { " " } else { ", " })?;
+            match kind {
+                OperandKind::Register => write!(f, "{}", REGISTERS[*arg as usize])?,
+                OperandKind::Immediate => write!(f, "{:+}", arg)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets register `args[0]` to `value`, once the program counter has moved
+fn set(args: &[i64; 2], state: &mut State, value: i64) -> Result<(), Fault> {
+    state.jump(1)?;
+    *state.register_mut(args[0]) = value;
+    Ok(())
+}
+
+fn hlf(args: &[i64; 2], state: &mut State, _overflow: Overflow) -> Result<(), Fault> {
+    set(args, state, state.register(args[0]) / 2)
+}
+
+fn tpl(args: &[i64; 2], state: &mut State, overflow: Overflow) -> Result<(), Fault> {
+    set(args, state, overflow.mul(state.register(args[0]), 3)?)
+}
+
+fn mul(args: &[i64; 2], state: &mut State, overflow: Overflow) -> Result<(), Fault> {
+    set(args, state, overflow.mul(state.register(args[0]), args[1])?)
+}
+
+fn load(args: &[i64; 2], state: &mut State, _overflow: Overflow) -> Result<(), Fault> {
+    set(args, state, args[1])
+}
+
+fn cpy(args: &[i64; 2], state: &mut State, _overflow: Overflow) -> Result<(), Fault> {
+    set(args, state, state.register(args[1]))
+}
+
+fn out(args: &[i64; 2], state: &mut State, _overflow: Overflow) -> Result<(), Fault> {
+    state.jump(1)?;
+    let value = state.register(args[0]);
+    state.output.push(value);
+    Ok(())
+}
+
+fn jz(args: &[i64; 2], state: &mut State, _overflow: Overflow) -> Result<(), Fault> {
+    match state.register(args[0]) {
+        0 => state.jump(args[1]),
+        _ => state.jump(1),
+    }
+}
+
+fn jnz(args: &[i64; 2], state: &mut State, _overflow: Overflow) -> Result<(), Fault> {
+    match state.register(args[0]) {
+        0 => state.jump(1),
+        _ => state.jump(args[1]),
+    }
+}
+
+pub static HLF: Opcode = Opcode { name: "hlf", code: 16, operands: &[OperandKind::Register], branch: None, exec: hlf };
+pub static TPL: Opcode = Opcode { name: "tpl", code: 17, operands: &[OperandKind::Register], branch: None, exec: tpl };
+pub static MUL: Opcode = Opcode { name: "mul", code: 18, operands: &[OperandKind::Register, OperandKind::Immediate], branch: None, exec: mul };
+pub static OUT: Opcode = Opcode { name: "out", code: 19, operands: &[OperandKind::Register], branch: None, exec: out };
+pub static JZ: Opcode = Opcode { name: "jz", code: 20, operands: &[OperandKind::Register, OperandKind::Immediate], branch: Some(1), exec: jz };
+pub static JNZ: Opcode = Opcode { name: "jnz", code: 21, operands: &[OperandKind::Register, OperandKind::Immediate], branch: Some(1), exec: jnz };
+pub static SET: Opcode = Opcode { name: "set", code: 22, operands: &[OperandKind::Register, OperandKind::Immediate], branch: None, exec: load };
+pub static CPY: Opcode = Opcode { name: "cpy", code: 23, operands: &[OperandKind::Register, OperandKind::Register], branch: None, exec: cpy };
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    DuplicateName(&'static str),
+    DuplicateCode(u8),
+    ReservedCode(u8),
+    TooManyOperands(&'static str),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::DuplicateName(name) => write!(f, "an instruction named '{}' already exists", name),
+            RegistryError::DuplicateCode(code) => write!(f, "opcode {} is already taken", code),
+            RegistryError::ReservedCode(code) => write!(f, "opcode {} is reserved for the core instructions", code),
+            RegistryError::TooManyOperands(name) => write!(f, "'{}' takes more than two operands", name),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// The extended instructions a parser or loader knows about
+#[derive(Debug, Clone)]
+pub struct Registry {
+    opcodes: Vec<&'static Opcode>,
+}
+
+impl Registry {
+    /// A registry with no extended instructions at all
+    pub fn empty() -> Self {
+        Registry { opcodes: vec![] }
+    }
+
+    pub fn register(&mut self, opcode: &'static Opcode) -> Result<(), RegistryError> {
+        if ["nop", "acc", "jmp"].contains(&opcode.name) || self.by_name(opcode.name).is_some() {
+            return Err(RegistryError::DuplicateName(opcode.name))
+        }
+        if opcode.code < FIRST_EXTENDED {
+            return Err(RegistryError::ReservedCode(opcode.code))
+        }
+        if self.by_code(opcode.code).is_some() {
+            return Err(RegistryError::DuplicateCode(opcode.code))
+        }
+        if opcode.operands.len() > 2 {
+            return Err(RegistryError::TooManyOperands(opcode.name))
+        }
+
+        self.opcodes.push(opcode);
+        Ok(())
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&'static Opcode> {
+        self.opcodes.iter().find(|opcode| opcode.name == name).copied()
+    }
+
+    pub fn by_code(&self, code: u8) -> Option<&'static Opcode> {
+        self.opcodes.iter().find(|opcode| opcode.code == code).copied()
+    }
+}
+
+/// The built-in extended instructions
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        for opcode in [&HLF, &TPL, &MUL, &OUT, &JZ, &JNZ, &SET, &CPY].iter() {
+            registry.register(opcode).expect("built-in opcodes are distinct");
+        }
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::{ExitResult, Instr, Program};
+
+    fn inc(args: &[i64; 2], state: &mut State, overflow: Overflow) -> Result<(), Fault> {
+        set(args, state, overflow.add(state.register(args[0]), 1)?)
+    }
+
+    static INC: Opcode = Opcode { name: "inc", code: 100, operands: &[OperandKind::Register], branch: None, exec: inc };
+
+    #[test]
+    fn extended_program() {
+        let text = "acc +2\ntpl acc\nhlf acc\nmul acc, 5\nout acc\njnz b, +2\nout b\njz b, +2\nacc +100\n";
+        let mut program: Program = text.parse().unwrap();
+        assert_eq!(ExitResult::Terminated, program.run_to_completion());
+        assert_eq!(15, program.state().acc);
+        assert_eq!(vec![15, 0], program.state().output);
+        assert_eq!("mul acc, +5", program.code()[3].to_string());
+        assert_eq!(Ok(program.code()[3]), "mul acc, 5".parse::<Instr>());
+
+        assert!("mul e, 5".parse::<Instr>().is_err());
+        assert!("mul a".parse::<Instr>().is_err());
+        assert!("inc a".parse::<Instr>().is_err());
+    }
+
+    #[test]
+    fn register_operands_are_checked() {
+        assert!(Op::new(&MUL, [9, 0]).is_none());
+        assert!(Op::new(&MUL, [-1, 0]).is_none());
+        assert!(Op::new(&CPY, [1, 5]).is_none());
+        assert_eq!(Some("mul d, +9".to_string()), Op::new(&MUL, [4, 9]).map(|op| op.to_string()));
+        assert_eq!(Op::new(&OUT, [1, 0]), Op::new(&OUT, [1, 7]));
+    }
+
+    #[test]
+    fn register_loads() {
+        let mut program: Program = "set a, 3\ncpy b, a\nmul b, -2\ncpy acc, b\nout a\nset a, -1\nout a\n".parse().unwrap();
+        assert_eq!(ExitResult::Terminated, program.run_to_completion());
+        assert_eq!(-6, program.state().acc);
+        assert_eq!([-1, -6, 0, 0], program.state().regs);
+        assert_eq!(vec![3, -1], program.state().output);
+        assert_eq!("cpy acc, b", program.code()[3].to_string());
+        assert_eq!("set a, -1", program.code()[5].to_string());
+        assert!("cpy a, 1".parse::<Instr>().is_err());
+    }
+
+    #[test]
+    fn custom_opcode() {
+        let mut registry = Registry::default();
+        registry.register(&INC).unwrap();
+        assert_eq!(Err(RegistryError::DuplicateName("inc")), registry.register(&INC));
+        let half = |code| &*Box::leak(Box::new(Opcode { name: "half", code, ..HLF }));
+        assert_eq!(Err(RegistryError::DuplicateCode(16)), registry.register(half(16)));
+        assert_eq!(Err(RegistryError::ReservedCode(2)), Registry::empty().register(half(2)));
+
+        let mut program = Program::parse_with("inc a\ninc a\ntpl a\nout a\n", &registry).unwrap();
+        assert_eq!(ExitResult::Terminated, program.run_to_completion());
+        assert_eq!(vec![6], program.state().output);
+        assert!(Program::parse_with("tpl a\n", &Registry::empty()).is_err());
+    }
+}
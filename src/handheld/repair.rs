@@ -0,0 +1,274 @@
+//! Fixing a looping program by flipping a single `nop` or `jmp`, found with
+//! one pass over its control-flow graph instead of re-running it per flip.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::Instr;
+
+/// Flipping the instruction at `pc` to `replacement` makes the program
+/// terminate with `acc` in the accumulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repair {
+    pub pc: usize,
+    pub replacement: Instr,
+    pub acc: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairError {
+    AlreadyTerminates { acc: i64 },
+    NoRepair,
+    /// Extended instructions can branch or change `acc` in ways the
+    /// analysis does not follow
+    Unsupported { pc: usize },
+}
+
+impl fmt::Display for RepairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RepairError::AlreadyTerminates { acc } => write!(f, "the program already terminates with acc {}", acc),
+            RepairError::NoRepair => write!(f, "no single flip makes the program terminate"),
+            RepairError::Unsupported { pc } => write!(f, "cannot analyse the extended instruction at {}", pc),
+        }
+    }
+}
+
+impl std::error::Error for RepairError {}
+
+/// Where control goes after `instr` at `pc`, if anywhere at or after 0.
+/// Extended instructions are taken not to branch.
+pub fn successor(pc: usize, instr: Instr) -> Option<usize> {
+    match instr {
+        Instr::Jmp(offset) => (pc as i64).checked_add(offset).and_then(|target| usize::try_from(target).ok()),
+        Instr::Nop(_) | Instr::Acc(_) | Instr::Op(_) => Some(pc + 1),
+    }
+}
+
+/// `nop` and `jmp` swapped, keeping the operand
+pub fn flip(instr: Instr) -> Option<Instr> {
+    match instr {
+        Instr::Nop(n) => Some(Instr::Jmp(n)),
+        Instr::Jmp(n) => Some(Instr::Nop(n)),
+        Instr::Acc(_) | Instr::Op(_) => None,
+    }
+}
+
+fn gain(instr: Instr) -> i64 {
+    match instr {
+        Instr::Acc(n) => n,
+        Instr::Nop(_) | Instr::Jmp(_) | Instr::Op(_) => 0,
+    }
+}
+
+/// What running from some pc to the end adds to the accumulator, and the
+/// lowest and highest it adds along the way, so a run starting from any
+/// `acc` can be checked for overflow without repeating it
+#[derive(Debug, Clone, Copy)]
+struct Rest {
+    total: i128,
+    low: i128,
+    high: i128,
+    /// The first instruction of the original run that the way to the end
+    /// goes through, if any
+    via: Option<usize>,
+}
+
+impl Rest {
+    /// The accumulator at the end of a run reaching here with `acc`, or
+    /// `None` if it would leave the `i64` range on the way
+    fn finish(&self, acc: i64) -> Option<i64> {
+        let acc = i128::from(acc);
+        match acc + self.low >= i128::from(i64::MIN) && acc + self.high <= i128::from(i64::MAX) {
+            true => i64::try_from(acc + self.total).ok(),
+            false => None,
+        }
+    }
+}
+
+/// The instructions the unmodified code runs from pc 0, in order, until it
+/// leaves the program or comes back to one of them, ignoring overflow
+fn run_path(code: &[Instr]) -> Vec<usize> {
+    let mut path = vec![];
+    let mut seen = vec![false; code.len()];
+    let mut pc = 0;
+    while pc < code.len() && !std::mem::replace(&mut seen[pc], true) {
+        path.push(pc);
+        pc = match successor(pc, code[pc]) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    path
+}
+
+/// For every pc, what running the unmodified code from there does to the
+/// accumulator before terminating, or `None` if it never terminates.
+/// `order` gives each instruction's position on the original run.
+fn to_end(code: &[Instr], order: &[Option<usize>]) -> Vec<Option<Rest>> {
+    let end = code.len();
+    let mut predecessors = vec![vec![]; end + 1];
+    for (pc, instr) in code.iter().enumerate() {
+        if let Some(target) = successor(pc, *instr).filter(|target| *target <= end) {
+            predecessors[target].push(pc);
+        }
+    }
+
+    let mut to_end = vec![None; end + 1];
+    to_end[end] = Some(Rest { total: 0, low: 0, high: 0, via: None });
+    let mut queue = vec![end];
+    while let Some(pc) = queue.pop() {
+        let rest = to_end[pc].expect("queued instructions terminate");
+        for &previous in &predecessors[pc] {
+            let gain = i128::from(gain(code[previous]));
+            to_end[previous] = Some(Rest {
+                total: gain + rest.total,
+                low: 0.min(gain + rest.low),
+                high: 0.max(gain + rest.high),
+                via: order[previous].map(|_| previous).or(rest.via),
+            });
+            queue.push(previous);
+        }
+    }
+
+    to_end
+}
+
+/// Every single flip along the path the program actually takes that makes
+/// it terminate, in the order the path reaches them. The accumulator is
+/// checked as with `Overflow::Trap`: a flip whose run would overflow is not
+/// a repair, and no flip after an overflow on the original path is reached.
+pub fn repairs(code: &[Instr]) -> Result<Vec<Repair>, RepairError> {
+    if let Some(pc) = code.iter().position(|instr| matches!(instr, Instr::Op(_))) {
+        return Err(RepairError::Unsupported { pc })
+    }
+
+    let path = run_path(code);
+    let mut order = vec![None; code.len()];
+    for (i, pc) in path.iter().enumerate() {
+        order[*pc] = Some(i);
+    }
+
+    let to_end = to_end(code, &order);
+    if let Some(acc) = to_end[0].and_then(|rest| rest.finish(0)) {
+        return Err(RepairError::AlreadyTerminates { acc })
+    }
+
+    let mut repairs = vec![];
+    let mut acc = 0i64;
+    for (i, &pc) in path.iter().enumerate() {
+        let instr = code[pc];
+        if let Some(replacement) = flip(instr) {
+            // The way to the end was found on the unflipped code. When the
+            // original run only fails by overflowing, that way can rejoin the
+            // run before this flip and come back here, so it would loop.
+            let rest = successor(pc, replacement)
+                .and_then(|target| to_end.get(target).copied().flatten())
+                .filter(|rest| rest.via.and_then(|via| order[via]).is_none_or(|j| j > i));
+            if let Some(acc) = rest.and_then(|rest| rest.finish(acc)) {
+                repairs.push(Repair { pc, replacement, acc });
+            }
+        }
+
+        acc = match acc.checked_add(gain(instr)) {
+            Some(acc) => acc,
+            None => break,
+        };
+    }
+
+    match repairs.is_empty() {
+        true => Err(RepairError::NoRepair),
+        false => Ok(repairs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::{ExitResult, Program, EXAMPLE};
+
+    fn brute_force(code: &[Instr]) -> Vec<Repair> {
+        let original = Program::new(code.to_vec());
+        (0..code.len())
+            .filter_map(|pc| {
+                let replacement = flip(code[pc])?;
+                let mut program = original.clone();
+                program.patch(pc, replacement);
+                match program.run_to_completion() {
+                    ExitResult::Terminated => Some(Repair { pc, replacement, acc: program.state().acc }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repairs_example() {
+        let program: Program = EXAMPLE.parse().unwrap();
+        assert_eq!(Ok(vec![Repair { pc: 7, replacement: Instr::Nop(-4), acc: 8 }]), repairs(&program.code()));
+    }
+
+    #[test]
+    fn repair_errors() {
+        assert_eq!(Err(RepairError::AlreadyTerminates { acc: 3 }), repairs(&[Instr::Acc(3), Instr::Nop(-1)]));
+        assert_eq!(Err(RepairError::NoRepair), repairs(&[Instr::Acc(3), Instr::Jmp(-1), Instr::Jmp(-2)]));
+        assert_eq!(Err(RepairError::Unsupported { pc: 1 }), repairs(&"acc +1\nout acc\njmp -2\n".parse::<Program>().unwrap().code()));
+        assert_eq!(Ok(vec![Repair { pc: 1, replacement: Instr::Nop(-1), acc: 3 }]), repairs(&[Instr::Acc(3), Instr::Jmp(-1)]));
+    }
+
+    #[test]
+    fn repairs_overflow() {
+        assert_eq!(Err(RepairError::NoRepair), repairs(&[Instr::Acc(i64::MAX), Instr::Acc(1)]));
+        assert_eq!(Err(RepairError::NoRepair), repairs(&[Instr::Acc(i64::MAX), Instr::Acc(1), Instr::Jmp(-2)]));
+
+        // Jumping over `acc -1` would overflow on the way, even though the
+        // accumulator ends back in range
+        let code = [Instr::Nop(2), Instr::Acc(-1), Instr::Acc(i64::MAX), Instr::Acc(1), Instr::Acc(-1), Instr::Jmp(-5)];
+        assert_eq!(Ok(vec![Repair { pc: 5, replacement: Instr::Nop(-5), acc: i64::MAX - 1 }]), repairs(&code));
+        assert_eq!(brute_force(&code), repairs(&code).unwrap());
+
+        // The original run reaches the end but overflows, and the way from
+        // the flipped jump's target to the end leads back through the flip
+        let code = [Instr::Acc(i64::MAX), Instr::Jmp(3), Instr::Acc(-5), Instr::Jmp(-2), Instr::Acc(1)];
+        assert_eq!(Err(RepairError::NoRepair), repairs(&code));
+        assert!(brute_force(&code).is_empty());
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut seed = 17u64;
+        let mut random = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+
+        for _ in 0..400 {
+            let len = 1 + random(12) as i64;
+            // With only forward jumps the program always reaches the end, so
+            // it can only fail by overflowing
+            let forward = random(2) == 0;
+            let code = (0..len)
+                .map(|_| {
+                    let operand = random(2 * len as u64 + 1) as i64 - len;
+                    match random(3) {
+                        0 => Instr::Nop(operand),
+                        1 if random(4) == 0 => Instr::Acc(if operand < 0 { i64::MIN - operand } else { i64::MAX - operand }),
+                        1 => Instr::Acc(operand),
+                        _ if forward => Instr::Jmp(1 + operand.abs()),
+                        _ => Instr::Jmp(operand),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            let mut program = Program::new(code.clone());
+            match program.run_to_completion() {
+                ExitResult::Terminated => assert_eq!(Err(RepairError::AlreadyTerminates { acc: program.state().acc }), repairs(&code)),
+                _ => {
+                    let expected = brute_force(&code);
+                    let found = repairs(&code).unwrap_or_default();
+                    assert_eq!(expected, found, "{:?}", code);
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Saving a run part way through and resuming it later, possibly in another
+//! process. Snapshots are plain text, one field per line, followed by one
+//! line per patched instruction:
+//!
+//! ```text
+//! handheld snapshot 2
+//! code 9 11117806f8a8c632
+//! overflow trap
+//! pc 6
+//! acc 1
+//! regs 0 0 0 0
+//! output
+//! visited 0 1 2
+//! patch 7 nop -4
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use super::{invalid_data, parse, Instr, Overflow, State};
+use super::opcodes::Registry;
+
+const HEADER: &str = "handheld snapshot 2";
+
+/// The state of a run, along with the instructions that already ran so
+/// loop detection carries on where it stopped, and enough about the program
+/// to only resume it on the same one
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Snapshot {
+    /// Number of instructions in the base code
+    pub len: usize,
+    /// `fingerprint` of the base code
+    pub fingerprint: u64,
+    pub patches: BTreeMap<usize, Instr>,
+    pub overflow: Overflow,
+    pub state: State,
+    pub visited: BTreeSet<usize>,
+}
+
+/// A 64-bit FNV-1a hash of the bytecode of `code`, the same in every process
+pub fn fingerprint(code: &[Instr]) -> u64 {
+    let mut bytes = vec![];
+    for instr in code {
+        instr.write(&mut bytes).expect("writing to a Vec never fails");
+    }
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn join<T: ToString>(values: impl IntoIterator<Item = T>) -> String {
+    values.into_iter().map(|v| format!(" {}", v.to_string())).collect()
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "code {} {:016x}", self.len, self.fingerprint)?;
+        writeln!(f, "overflow {}", self.overflow)?;
+        writeln!(f, "pc {}", self.state.pc)?;
+        writeln!(f, "acc {}", self.state.acc)?;
+        writeln!(f, "regs{}", join(self.state.regs.iter()))?;
+        writeln!(f, "output{}", join(self.state.output.iter()))?;
+        writeln!(f, "visited{}", join(self.visited.iter()))?;
+        self.patches.iter().try_for_each(|(pc, instr)| writeln!(f, "patch {} {}", pc, instr))
+    }
+}
+
+/// Parses with the built-in extended instructions
+impl FromStr for Snapshot {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Snapshot::parse_with(s, &Registry::default())
+    }
+}
+
+impl Snapshot {
+    /// Parses like `from_str`, reading patches with the extended
+    /// instructions in `registry`
+    pub fn parse_with(s: &str, registry: &Registry) -> Result<Self, io::Error> {
+        let mut lines = s.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid_data(format!("expected '{}' on the first line", HEADER)))
+        }
+
+        let mut field = |name: &str| {
+            let line = lines.next().unwrap_or_default();
+            match line.split_whitespace().collect::<Vec<_>>().split_first() {
+                Some((key, values)) if *key == name => Ok(values.join(" ")),
+                _ => Err(invalid_data(format!("expected the {} field, got '{}'", name, line))),
+            }
+        };
+        fn numbers<T: FromStr>(name: &str, values: &str) -> Result<Vec<T>, io::Error> {
+            values.split_whitespace()
+                .map(|v| v.parse().map_err(|_| invalid_data(format!("invalid {} '{}'", name, v))))
+                .collect()
+        }
+
+        let code = field("code")?;
+        let (len, fingerprint) = match code.split_once(' ') {
+            Some((len, fingerprint)) => (len.parse().ok(), u64::from_str_radix(fingerprint, 16).ok()),
+            None => (None, None),
+        };
+        let (len, fingerprint) = len.zip(fingerprint).ok_or_else(|| invalid_data(format!("invalid code '{}'", code)))?;
+        let overflow = field("overflow")?.parse::<Overflow>().map_err(|e| invalid_data(e.to_string()))?;
+        let pc = field("pc")?;
+        let pc = pc.parse().map_err(|_| invalid_data(format!("invalid pc '{}'", pc)))?;
+        let acc = field("acc")?;
+        let acc = acc.parse().map_err(|_| invalid_data(format!("invalid acc '{}'", acc)))?;
+        let regs = numbers::<i64>("register", &field("regs")?)?;
+        let mut state = State { pc, acc, ..State::default() };
+        if regs.len() != state.regs.len() {
+            return Err(invalid_data(format!("expected {} registers, got {}", state.regs.len(), regs.len())))
+        }
+        state.regs.copy_from_slice(&regs);
+        state.output = numbers("output", &field("output")?)?;
+        let visited = numbers("pc", &field("visited")?)?.into_iter().collect();
+
+        let mut patches = BTreeMap::new();
+        for line in lines {
+            let patch = line.strip_prefix("patch ").and_then(|patch| patch.split_once(' '));
+            let patch = patch.and_then(|(pc, instr)| Some((pc.parse().ok()?, parse::instr(instr, registry)?)));
+            let (pc, instr) = patch.ok_or_else(|| invalid_data(format!("expected a patch, got '{}'", line)))?;
+            patches.insert(pc, instr);
+        }
+
+        Ok(Snapshot { len, fingerprint, patches, overflow, state, visited })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::{ExitResult, Program, EXAMPLE};
+
+    #[test]
+    fn resumes_from_text() {
+        let mut program: Program = EXAMPLE.parse().unwrap();
+        assert_eq!(ExitResult::StepLimit, program.run_with_limit(3));
+        let text = program.snapshot().to_string();
+        let code = format!("code 9 {:016x}", fingerprint(&program.code()));
+        assert_eq!(format!("handheld snapshot 2\n{}\noverflow trap\npc 6\nacc 1\nregs 0 0 0 0\noutput\nvisited 0 1 2\n", code), text);
+
+        let mut resumed: Program = EXAMPLE.parse().unwrap();
+        resumed.restore(text.parse().unwrap()).unwrap();
+        assert_eq!(ExitResult::InfiniteLoop { pc: 1 }, resumed.run_to_completion());
+        assert_eq!(5, resumed.state().acc);
+
+        assert_eq!(ExitResult::StepLimit, program.run_with_limit(2));
+        assert_eq!(ExitResult::InfiniteLoop { pc: 1 }, program.run_with_limit(2));
+        assert_eq!(resumed, program);
+    }
+
+    #[test]
+    fn keeps_registers_and_output() {
+        let snapshot = Snapshot {
+            len: 12,
+            fingerprint: u64::MAX,
+            patches: vec![(3, Instr::Acc(i64::MIN)), (5, "mul b, -3".parse().unwrap())].into_iter().collect(),
+            overflow: Overflow::Saturate,
+            state: State { pc: 3, acc: -7, regs: [1, -2, 3, 0], output: vec![15, 0] },
+            visited: vec![0, 2].into_iter().collect(),
+        };
+        assert_eq!(snapshot, snapshot.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn only_restores_the_same_program() {
+        let mut program: Program = EXAMPLE.parse().unwrap();
+        program.patch(7, Instr::Nop(-4));
+        program.run_with_limit(3);
+        let snapshot = program.snapshot();
+
+        let mut other = program.clone();
+        other.reset();
+        assert!(other.restore(snapshot.clone()).is_ok());
+        assert_eq!(program, other);
+
+        let mut unpatched: Program = EXAMPLE.parse().unwrap();
+        assert!(unpatched.restore(snapshot.clone()).is_err());
+        let mut edited: Program = EXAMPLE.replace("acc +6", "acc +7").parse().unwrap();
+        edited.patch(7, Instr::Nop(-4));
+        assert!(edited.restore(snapshot.clone()).is_err());
+        let mut wrapping = program.clone();
+        wrapping.set_overflow(Overflow::Wrap);
+        assert!(wrapping.restore(snapshot).is_err());
+        assert_eq!(State::default(), unpatched.state().clone());
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let good = Snapshot::default().to_string();
+        assert!(good.replace("snapshot 2", "snapshot 1").parse::<Snapshot>().is_err());
+        assert!(good.replace("overflow trap", "overflow fold").parse::<Snapshot>().is_err());
+        assert!(good.replace("code 0 ", "code x ").parse::<Snapshot>().is_err());
+        assert!(format!("{}patch 1 hop +2\n", good).parse::<Snapshot>().is_err());
+        assert!(good.replace("regs 0 0 0 0", "regs 0 0").parse::<Snapshot>().is_err());
+        assert!(good.replace("acc 0", "acc x").parse::<Snapshot>().is_err());
+        assert!(good.replace("visited", "seen").parse::<Snapshot>().is_err());
+        assert!(good.lines().take(3).collect::<Vec<_>>().join("\n").parse::<Snapshot>().is_err());
+    }
+
+    #[test]
+    fn patches_share_code() {
+        let original: Program = EXAMPLE.parse().unwrap();
+        let mut patched = original.clone();
+        patched.patch(7, Instr::Nop(-4));
+
+        assert!(std::sync::Arc::ptr_eq(original.base(), patched.base()));
+        assert_eq!(Some(Instr::Jmp(-4)), original.instr_at(7));
+        assert_eq!(Some(Instr::Nop(-4)), patched.instr_at(7));
+        assert_eq!(Instr::Nop(-4), patched.code()[7]);
+        assert_eq!(ExitResult::Terminated, patched.run_to_completion());
+
+        patched.patch(7, Instr::Jmp(-4));
+        assert!(patched.patches().is_empty());
+    }
+}
@@ -0,0 +1,278 @@
+//! Recording which instructions a program ran, and replaying the record.
+//!
+//! Traces are either JSON lines, one object per executed instruction, or a
+//! binary stream starting with `MAGIC` followed by varint-encoded events.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+use super::{invalid_data, varint, Instr, Program, State};
+use super::opcodes::Registry;
+
+/// Starts every binary trace; the last byte is the format version
+pub const MAGIC: &[u8; 4] = b"HHT\x02";
+
+/// One executed instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub pc: usize,
+    pub instr: Instr,
+    pub acc_before: i64,
+    pub acc_after: i64,
+    /// Where the instruction sent the program, which for a conditional
+    /// jump depends on registers the trace does not record
+    pub pc_after: usize,
+}
+
+impl Event {
+    /// The program counter and accumulator right after the instruction ran.
+    /// Registers other than `acc` are not recorded and are left at zero.
+    pub fn after(&self) -> State {
+        State { pc: self.pc_after, acc: self.acc_after, ..State::default() }
+    }
+}
+
+/// Receives every instruction a program executes
+pub trait Tracer {
+    fn record(&mut self, event: &Event) -> io::Result<()>;
+}
+
+/// Records nothing
+impl Tracer for () {
+    fn record(&mut self, _event: &Event) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Records in memory
+impl Tracer for Vec<Event> {
+    fn record(&mut self, event: &Event) -> io::Result<()> {
+        self.push(*event);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    JsonLines,
+    Binary,
+}
+
+/// Records to a writer, e.g. a buffered file
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W, format: TraceFormat) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            out.write_all(MAGIC)?;
+        }
+        Ok(TraceWriter { out, format })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn record(&mut self, event: &Event) -> io::Result<()> {
+        match self.format {
+            TraceFormat::JsonLines => writeln!(
+                self.out,
+                r#"{{"pc":{},"instr":"{}","acc_before":{},"acc_after":{},"pc_after":{}}}"#,
+                event.pc, event.instr, event.acc_before, event.acc_after, event.pc_after,
+            ),
+            TraceFormat::Binary => {
+                varint::write_u64(&mut self.out, event.pc as u64)?;
+                event.instr.write(&mut self.out)?;
+                varint::write_i64(&mut self.out, event.acc_before)?;
+                varint::write_i64(&mut self.out, event.acc_after)?;
+                varint::write_u64(&mut self.out, event.pc_after as u64)
+            },
+        }
+    }
+}
+
+/// Splits on the commas between fields, not those inside strings
+fn fields(body: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let (mut start, mut quoted) = (0, false);
+    for (i, c) in body.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(&body[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    fields.push(&body[start..]);
+    fields
+}
+
+fn json_event(line: &str, registry: &Registry) -> Option<Event> {
+    let body = line.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let (mut pc, mut instr, mut acc_before, mut acc_after, mut pc_after) = (None, None, None, None, None);
+    for field in fields(body) {
+        let (key, value) = field.split_once(':')?;
+        let value = value.trim();
+        match key.trim().trim_matches('"') {
+            "pc" => pc = value.parse().ok(),
+            "instr" => instr = Program::parse_with(value.strip_prefix('"')?.strip_suffix('"')?, registry).ok()?.instr_at(0),
+            "acc_before" => acc_before = value.parse().ok(),
+            "acc_after" => acc_after = value.parse().ok(),
+            "pc_after" => pc_after = value.parse().ok(),
+            _ => return None,
+        }
+    }
+
+    Some(Event { pc: pc?, instr: instr?, acc_before: acc_before?, acc_after: acc_after?, pc_after: pc_after? })
+}
+
+fn binary_event<R: BufRead>(input: &mut R, registry: &Registry) -> io::Result<Event> {
+    let pc = varint::read_u64(input)? as usize;
+    let instr = Instr::read(input, registry)?;
+    let acc_before = varint::read_i64(input)?;
+    let acc_after = varint::read_i64(input)?;
+    let pc_after = varint::read_u64(input)? as usize;
+
+    Ok(Event { pc, instr, acc_before, acc_after, pc_after })
+}
+
+/// Reads a trace in either format, telling them apart by `MAGIC`, with the
+/// extended instructions in `registry`
+pub fn read_trace<R: BufRead>(mut input: R, registry: &Registry) -> io::Result<Vec<Event>> {
+    let header = input.fill_buf()?;
+    if header.starts_with(&MAGIC[..3]) && !header.starts_with(MAGIC) {
+        return Err(invalid_data(format!("unsupported trace version {}, expected {}", header.get(3).copied().unwrap_or(0), MAGIC[3])))
+    }
+    if header.starts_with(MAGIC) {
+        input.consume(MAGIC.len());
+        let mut events = vec![];
+        while !input.fill_buf()?.is_empty() {
+            events.push(binary_event(&mut input, registry)?);
+        }
+        return Ok(events)
+    }
+
+    input.lines().enumerate()
+        .filter(|(_, l)| l.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(n, l)| json_event(&l?, registry).ok_or_else(|| invalid_data(format!("line {}: invalid trace event", n + 1))))
+        .collect()
+}
+
+/// A recorded run, which can be inspected at any step
+pub struct Replay {
+    events: Vec<Event>,
+}
+
+impl Replay {
+    pub fn new(events: Vec<Event>) -> Self {
+        Replay { events }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The program counter and accumulator before the `step`th instruction
+    /// ran, or after the last one for `step == len()`
+    pub fn state_at(&self, step: usize) -> Option<State> {
+        match self.events.get(step) {
+            Some(event) => Some(State { pc: event.pc, acc: event.acc_before, ..State::default() }),
+            None if step == self.len() => Some(self.events.last().map_or_else(State::default, Event::after)),
+            None => None,
+        }
+    }
+
+    /// How many times each instruction ran, along with the instruction
+    pub fn hit_counts(&self) -> BTreeMap<usize, (Instr, usize)> {
+        let mut hits = BTreeMap::new();
+        for event in &self.events {
+            hits.entry(event.pc).or_insert((event.instr, 0)).1 += 1;
+        }
+        hits
+    }
+
+    /// The instructions, in order, that would run again next if the trace
+    /// stopped because of an infinite loop
+    pub fn loop_body(&self) -> Option<Vec<usize>> {
+        let next = self.state_at(self.len())?.pc;
+        let first = self.events.iter().position(|event| event.pc == next)?;
+        Some(self.events[first..].iter().map(|event| event.pc).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handheld::{ExitResult, Program, EXAMPLE};
+
+    fn events() -> Vec<Event> {
+        let mut program: Program = EXAMPLE.parse().unwrap();
+        let mut events = vec![];
+        assert_eq!(ExitResult::InfiniteLoop { pc: 1 }, program.run_traced(None, &mut events).unwrap());
+        events
+    }
+
+    #[test]
+    fn records_events() {
+        let events = events();
+        assert_eq!(7, events.len());
+        assert_eq!(Event { pc: 1, instr: Instr::Acc(1), acc_before: 0, acc_after: 1, pc_after: 2 }, events[1]);
+        assert_eq!(State { pc: 1, acc: 5, ..State::default() }, events[6].after());
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let mut events = events();
+        let mut program: Program = "acc +3\nmul acc, -2\nout acc\n".parse().unwrap();
+        program.run_traced(None, &mut events).unwrap();
+        for format in [TraceFormat::JsonLines, TraceFormat::Binary].iter() {
+            let mut writer = TraceWriter::new(vec![], *format).unwrap();
+            for event in &events {
+                writer.record(event).unwrap();
+            }
+            let bytes = writer.into_inner();
+            assert_eq!(events, read_trace(&bytes[..], &Registry::default()).unwrap());
+            assert!(read_trace(&bytes[..], &Registry::empty()).is_err());
+        }
+
+        let mut writer = TraceWriter::new(vec![], TraceFormat::JsonLines).unwrap();
+        writer.record(&events[1]).unwrap();
+        assert_eq!("{\"pc\":1,\"instr\":\"acc +1\",\"acc_before\":0,\"acc_after\":1,\"pc_after\":2}\n", String::from_utf8(writer.into_inner()).unwrap());
+
+        assert!(read_trace(&b"{\"pc\":1}\n"[..], &Registry::default()).is_err());
+        assert!(read_trace(&b"HHT\x02\x00\x07\x00\x00\x00\x01"[..], &Registry::default()).is_err());
+        assert!(read_trace(&b"HHT\x02\x00"[..], &Registry::default()).is_err());
+        assert!(read_trace(&b"HHT\x01\x00\x00\x00\x00"[..], &Registry::default()).is_err());
+    }
+
+    #[test]
+    fn replay() {
+        let replay = Replay::new(events());
+        assert_eq!(Some(State { pc: 6, acc: 1, ..State::default() }), replay.state_at(3));
+        assert_eq!(Some(State { pc: 1, acc: 5, ..State::default() }), replay.state_at(7));
+        assert_eq!(None, replay.state_at(8));
+        assert_eq!(Some(&(Instr::Acc(1), 1)), replay.hit_counts().get(&6));
+        assert_eq!(Some(vec![1, 2, 6, 7, 3, 4]), replay.loop_body());
+
+        let replay = Replay::new(events()[..3].to_vec());
+        assert_eq!(None, replay.loop_body());
+
+        let mut program: Program = "set a, 1\njnz a, -1\n".parse().unwrap();
+        let mut events = vec![];
+        assert_eq!(ExitResult::InfiniteLoop { pc: 0 }, program.run_traced(None, &mut events).unwrap());
+        let replay = Replay::new(events);
+        assert_eq!(Some(State::default()), replay.state_at(2));
+        assert_eq!(Some(vec![0, 1]), replay.loop_body());
+    }
+}
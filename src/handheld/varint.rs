@@ -0,0 +1,61 @@
+//! LEB128 variable-length integers, zigzag-encoded when signed
+
+use std::io::{self, Read, Write};
+
+use super::invalid_data;
+
+pub fn write_u64<W: Write>(out: &mut W, mut n: u64) -> io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return out.write_all(&[byte])
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        n |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(n)
+        }
+    }
+
+    Err(invalid_data("varint is longer than 64 bits".to_string()))
+}
+
+pub fn write_i64<W: Write>(out: &mut W, n: i64) -> io::Result<()> {
+    write_u64(out, ((n << 1) ^ (n >> 63)) as u64)
+}
+
+pub fn read_i64<R: Read>(input: &mut R) -> io::Result<i64> {
+    let n = read_u64(input)?;
+    Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let numbers = [0, 1, -1, 63, -64, 64, 300, -300, i64::MAX, i64::MIN];
+        let mut bytes = vec![];
+        for n in numbers.iter() {
+            write_i64(&mut bytes, *n).unwrap();
+        }
+        assert_eq!(&[0, 2, 1], &bytes[..3]);
+
+        let mut input = &bytes[..];
+        for n in numbers.iter() {
+            assert_eq!(*n, read_i64(&mut input).unwrap());
+        }
+        assert!(input.is_empty());
+        assert!(read_u64(&mut &[0x80][..]).is_err());
+    }
+}